@@ -1,32 +1,130 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
-use crate::parser::{BinOp, Expr, Stmt, Type, UnaryOp};
+use crate::{
+  error::CompileError,
+  parser::{BinOp, ConstValue, DEFAULT_INPUT_LEN, Expr, ExprKind, MatchArm, Stmt, StmtKind, Type, UnaryOp},
+  runtime::Routine,
+  scope::Scopes,
+};
+
+const TEMP_REGS: [&str; 7] = ["t0", "t1", "t2", "t3", "t4", "t5", "t6"];
+const ARG_REGS: [&str; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+/// Largest 16-byte aligned frame whose offsets all fit in a 12-bit signed immediate.
+const MAX_FRAME_SIZE: i32 = 2032;
+/// Fewest matched values for which a `match` dispatches through a jump table.
+const JUMP_TABLE_MIN_VALUES: i64 = 4;
+/// Most entries a jump table may have; a sparser or wider `match` uses a compare chain.
+const JUMP_TABLE_MAX_LEN: i64 = 1024;
+
+/// A register kept live while another sub-expression is evaluated.
+enum Held {
+  Reg(String),
+  /// Evicted by `alloc_reg`; the value sits in this frame slot until `unhold` reloads it.
+  Spilled(i32),
+}
+
+/// A variable's frame slot and the type of the value it currently holds.
+struct Var {
+  offset: i32,
+  ty: Type,
+}
+
+/// Where `break` and `continue` jump to inside a loop.
+struct LoopTargets {
+  label: Option<String>,
+  continue_label: String,
+  end_label: String,
+}
 
 pub struct CodeGen {
   strings: HashMap<String, String>,
-  vars: HashMap<String, i32>,
-  var_types: HashMap<String, Type>,
+  vars: Scopes<Var>,
   var_offset: i32,
   output: Vec<String>,
   reg_pool: Vec<String>,
+  reg_count: usize,
+  held: Vec<Held>,
+  /// Frame slots no longer in use: reloaded spills and variables whose block has ended.
+  free_slots: Vec<i32>,
   while_counter: usize,
+  /// Loops enclosing the statement being generated, innermost last.
+  loops: Vec<LoopTargets>,
   if_counter: usize,
+  match_counter: usize,
+  sc_counter: usize,
+  cond_counter: usize,
+  bool_counter: usize,
+  fn_types: HashMap<String, Type>,
+  current_fn: Option<String>,
+  /// Frame slots holding evaluated call arguments, one row per call nesting depth.
+  arg_slots: Vec<Vec<i32>>,
+  call_depth: usize,
+  /// Frame slots for temporaries that are live across a call.
+  save_slots: Vec<i32>,
+  /// Jump tables of `match` statements placed in `.data`, as (label, target of each entry).
+  jump_tables: Vec<(String, Vec<String>)>,
+  bounds_check: bool,
+  bounds_counter: usize,
+  uses_bounds_fail: bool,
+  /// Runtime library routines called so far, emitted after the functions.
+  routines: BTreeSet<Routine>,
+  /// First error found partway through an expression, returned once generation finishes.
+  error: Option<CompileError>,
+}
+
+impl Default for CodeGen {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl CodeGen {
   pub fn new() -> Self {
     CodeGen {
       strings: HashMap::new(),
-      vars: HashMap::new(),
-      var_types: HashMap::new(),
+      vars: Scopes::new(),
       var_offset: 0,
       output: Vec::new(),
-      reg_pool: ["t0", "t1", "t2", "t3", "t4", "t5", "t6"].iter().map(|&r| r.to_string()).collect(),
+      reg_pool: TEMP_REGS.iter().map(|&r| r.to_string()).collect(),
+      reg_count: TEMP_REGS.len(),
+      held: Vec::new(),
+      free_slots: Vec::new(),
       while_counter: 0,
+      loops: Vec::new(),
       if_counter: 0,
+      match_counter: 0,
+      sc_counter: 0,
+      cond_counter: 0,
+      bool_counter: 0,
+      fn_types: HashMap::new(),
+      current_fn: None,
+      arg_slots: Vec::new(),
+      call_depth: 0,
+      save_slots: Vec::new(),
+      jump_tables: Vec::new(),
+      bounds_check: true,
+      bounds_counter: 0,
+      uses_bounds_fail: false,
+      routines: BTreeSet::new(),
+      error: None,
     }
   }
 
+  /// Restricts expression evaluation to the first `count` temporaries (`t0` upwards),
+  /// which forces the spilling path to be taken sooner.
+  pub fn with_regs(mut self, count: usize) -> Self {
+    assert!((3..=TEMP_REGS.len()).contains(&count), "Compiler: register pool must hold 3 to 7 registers");
+    self.reg_count = count;
+    self.reg_pool = TEMP_REGS[..count].iter().map(|&r| r.to_string()).collect();
+    self
+  }
+
+  /// Enables or disables the runtime bounds check emitted for every array access.
+  pub fn with_bounds_check(mut self, enabled: bool) -> Self {
+    self.bounds_check = enabled;
+    self
+  }
+
   #[inline]
   fn nl(&mut self) {
     self.output.push(String::new());
@@ -34,57 +132,285 @@ impl CodeGen {
 
   fn alloc_reg(&mut self) -> String {
     if let Some(reg) = self.reg_pool.pop() {
-      reg
-    } else {
-      panic!("Compiler: No registers left to allocate") // Todo: stack spill
+      return reg;
     }
+
+    // Evict the oldest held value: it is the one that will be needed last.
+    let victim = self.held.iter().position(|h| matches!(h, Held::Reg(_)));
+    let Some(index) = victim else {
+      panic!("Compiler: No registers left to allocate");
+    };
+    let slot = self.alloc_free_slot();
+    let Held::Reg(reg) = std::mem::replace(&mut self.held[index], Held::Spilled(slot)) else {
+      unreachable!()
+    };
+    self.output.push(format!("  sw {}, {}(sp) # Spill {}", reg, slot, reg));
+    reg
   }
 
   fn free_reg(&mut self, reg: String) {
-    self.reg_pool.push(reg);
+    // `x0` stands in for a zero operand (see `gen_strcmp`) and is never pooled.
+    if reg != "x0" {
+      self.reg_pool.push(reg);
+    }
   }
 
-  pub fn generate(&mut self, stmts: &Vec<Stmt>) -> String {
-    self.output.push("  .text".to_string());
-    self.output.push("  .globl main".to_string());
-    self.output.push("main:".to_string());
-    self.output.push("  addi sp, sp, -512 # Set up stack frame".to_string());
-    self.nl();
+  /// Marks `reg` as live while other code is generated; get it back with `unhold`.
+  fn hold(&mut self, reg: String) {
+    self.held.push(Held::Reg(reg));
+  }
+
+  /// Returns the most recently held value, reloading it if it was spilled in the meantime.
+  /// The register may differ from the one passed to `hold`.
+  fn unhold(&mut self) -> String {
+    match self.held.pop().expect("Compiler: unhold without hold") {
+      Held::Reg(reg) => reg,
+      Held::Spilled(slot) => {
+        let reg = self.alloc_reg();
+        self.output.push(format!("  lw {}, {}(sp) # Reload spilled value", reg, slot));
+        self.free_slots.push(slot);
+        reg
+      }
+    }
+  }
+
+  /// Moves every held value to the frame. Used before code that only runs on some
+  /// paths, so a spill inside it cannot leave the paths disagreeing about where a
+  /// value lives when they join.
+  fn spill_all_held(&mut self) {
+    for index in 0..self.held.len() {
+      if let Held::Reg(_) = self.held[index] {
+        let slot = self.alloc_free_slot();
+        let Held::Reg(reg) = std::mem::replace(&mut self.held[index], Held::Spilled(slot)) else {
+          unreachable!()
+        };
+        self.output.push(format!("  sw {}, {}(sp) # Spill {}", reg, slot, reg));
+        self.free_reg(reg);
+      }
+    }
+  }
+
+  /// A slot for a value that is live only within the current block.
+  fn alloc_free_slot(&mut self) -> i32 {
+    match self.free_slots.pop() {
+      Some(slot) => slot,
+      None => self.alloc_slot(),
+    }
+  }
 
+  fn alloc_slot(&mut self) -> i32 {
+    let offset = self.var_offset;
+    self.var_offset += 4;
+    offset
+  }
+
+  fn arg_slot(&mut self, depth: usize, index: usize) -> i32 {
+    while self.arg_slots.len() <= depth {
+      self.arg_slots.push(Vec::new());
+    }
+    while self.arg_slots[depth].len() <= index {
+      let slot = self.alloc_slot();
+      self.arg_slots[depth].push(slot);
+    }
+    self.arg_slots[depth][index]
+  }
+
+  fn save_slot(&mut self, index: usize) -> i32 {
+    while self.save_slots.len() <= index {
+      let slot = self.alloc_slot();
+      self.save_slots.push(slot);
+    }
+    self.save_slots[index]
+  }
+
+  pub fn generate(&mut self, stmts: &Vec<Stmt>) -> Result<String, CompileError> {
     for stmt in stmts {
+      if let StmtKind::Function { name, ret, .. } = &stmt.kind {
+        self.fn_types.insert(name.clone(), ret.clone());
+      }
+    }
+
+    for stmt in stmts {
+      if matches!(stmt.kind, StmtKind::Function { .. }) {
+        continue;
+      }
       self.gen_stmt(stmt);
       self.nl();
     }
 
+    let body = std::mem::take(&mut self.output);
+    let frame_size = self.frame_size("main", 0)?;
+
+    self.output.push("  .text".to_string());
+    self.output.push("  .globl main".to_string());
+    self.output.push("main:".to_string());
+    if frame_size > 0 {
+      self.output.push(format!("  addi sp, sp, -{} # Set up stack frame", frame_size));
+    }
+    self.nl();
+    self.output.extend(body);
+
     self.output.push("  # Exit with code 0".to_string());
+    if frame_size > 0 {
+      self.output.push(format!("  addi sp, sp, {} # Tear down stack frame", frame_size));
+    }
     self.output.push("  li a0, 0 # Exit code 0".to_string());
     self.output.push("  li a7, 10 # Syscall 10: exit".to_string());
     self.output.push("  ecall".to_string());
 
+    for stmt in stmts {
+      if let StmtKind::Function { name, params, ret, body } = &stmt.kind {
+        self.nl();
+        self.gen_function(name, params, ret, body)?;
+      }
+    }
+
+    if self.uses_bounds_fail {
+      self.nl();
+      self.gen_bounds_fail();
+    }
+
+    for routine in std::mem::take(&mut self.routines) {
+      self.nl();
+      self.output.extend(routine.asm().lines().map(str::to_string));
+    }
+
     let mut final_out = Vec::new();
     final_out.push("  .data".to_string());
+    for (label, targets) in &self.jump_tables {
+      final_out.push("  .align 2".to_string());
+      final_out.push(format!("{}:", label));
+      for row in targets.chunks(8) {
+        final_out.push(format!("  .word {}", row.join(", ")));
+      }
+    }
     self.gen_strings(&mut final_out);
     final_out.push(String::new());
     final_out.append(&mut self.output);
 
-    final_out.join("\n")
+    if let Some(error) = self.error.take() {
+      return Err(error);
+    }
+
+    Ok(final_out.join("\n"))
+  }
+
+  /// Size of the current frame: every slot handed out by `alloc_slot` plus `reserved`
+  /// bytes at the top, rounded up to the 16-byte alignment the RISC-V psABI requires.
+  fn frame_size(&self, name: &str, reserved: i32) -> Result<i32, CompileError> {
+    let size = (self.var_offset + reserved + 15) & !15;
+    if size > MAX_FRAME_SIZE {
+      return Err(CompileError::CodegenError {
+        msg: format!(
+          "Stack frame of '{}' needs {} bytes, but sp-relative offsets are limited to {} bytes (12-bit immediate)",
+          name, size, MAX_FRAME_SIZE
+        ),
+      });
+    }
+    Ok(size)
+  }
+
+  fn gen_function(
+    &mut self,
+    name: &str,
+    params: &[(String, Type)],
+    ret: &Type,
+    body: &[Stmt],
+  ) -> Result<(), CompileError> {
+    self.vars = Scopes::new();
+    self.var_offset = 0;
+    self.arg_slots.clear();
+    self.save_slots.clear();
+    self.free_slots.clear();
+    self.current_fn = Some(name.to_string());
+    let outer = std::mem::take(&mut self.output);
+
+    for (i, (param, ty)) in params.iter().enumerate() {
+      let offset = self.alloc_slot();
+      self.vars.declare(param, Var { offset, ty: ty.clone() });
+      self.output.push(format!("  sw {}, {}(sp) # Store parameter {}", ARG_REGS[i], offset, param));
+    }
+    self.nl();
+
+    self.gen_block(body);
+
+    self.output.push("  # Fall through: return the default value".to_string());
+    self.gen_default_value(ret);
+
+    let body = std::mem::replace(&mut self.output, outer);
+    self.current_fn = None;
+    // ra and s0 are saved in the top 8 bytes of the frame.
+    let frame_size = self.frame_size(name, 8)?;
+
+    self.output.push(format!("fn_{}:", name));
+    self.output.push(format!("  addi sp, sp, -{} # Set up stack frame", frame_size));
+    self.output.push(format!("  sw ra, {}(sp) # Save return address", frame_size - 4));
+    self.output.push(format!("  sw s0, {}(sp) # Save frame pointer", frame_size - 8));
+    self.output.push(format!("  addi s0, sp, {} # New frame pointer", frame_size));
+    self.output.extend(body);
+    self.output.push(format!("fn_{}_ret:", name));
+    self.output.push(format!("  lw ra, {}(sp) # Restore return address", frame_size - 4));
+    self.output.push(format!("  lw s0, {}(sp) # Restore frame pointer", frame_size - 8));
+    self.output.push(format!("  addi sp, sp, {} # Tear down stack frame", frame_size));
+    self.output.push("  ret".to_string());
+
+    Ok(())
+  }
+
+  fn gen_default_value(&mut self, ty: &Type) {
+    match ty {
+      Type::Int => self.output.push("  li a0, 0 # Return 0".to_string()),
+      Type::Bool => self.output.push("  li a0, 0 # Return false".to_string()),
+      Type::Char => self.output.push("  li a0, 0 # Return '\\0'".to_string()),
+      Type::String => {
+        let label = self.ensure_string_label(&String::new());
+        self.output.push(format!("  la a0, {} # Return \"\"", label));
+      }
+      Type::Array => panic!("Compiler: Functions cannot return arrays"),
+    }
+  }
+
+  fn gen_bounds_fail(&mut self) {
+    let label = self.ensure_string_label(&"Runtime error: array index out of bounds\n".to_string());
+    self.output.push("rt_bounds_fail:".to_string());
+    self.output.push(format!("  la a0, {} # Load bounds error message", label));
+    self.output.push("  li a7, 4 # Syscall 4: print_string".to_string());
+    self.output.push("  ecall".to_string());
+    self.output.push("  li a0, 1 # Exit code 1".to_string());
+    self.output.push("  li a7, 10 # Syscall 10: exit".to_string());
+    self.output.push("  ecall".to_string());
   }
 
   fn gen_stmt(&mut self, stmt: &Stmt) {
-    match stmt {
-      Stmt::Assign { var, expr } => {
+    match &stmt.kind {
+      StmtKind::Let { var, expr } => {
         let reg = self.gen_expr(expr);
         let expr_type = self.infer_type(expr);
-        self.var_types.insert(var.clone(), expr_type);
-        if !self.vars.contains_key(var) {
-          self.vars.insert(var.clone(), self.var_offset);
-          self.var_offset += 4;
-        }
-        let offset = *self.vars.get(var).unwrap();
-        self.output.push(format!("  sw {}, {}(sp) # Store variable {}", reg, offset, var));
+        self.declare_var(var, reg, expr_type);
+      }
+      // Substituted wherever it is used, so it needs no slot.
+      StmtKind::Const { .. } => {}
+      StmtKind::Assign { var, expr } => {
+        let reg = self.gen_expr(expr);
+        let expr_type = self.infer_type(expr);
+        self.store_var(var, reg, expr_type);
+      }
+      StmtKind::CompoundAssign { var, op, expr } => {
+        let value = Expr::compound(var, stmt.span, op.clone(), expr.clone());
+        let reg = self.gen_expr(&value);
+        let value_type = self.infer_type(&value);
+        self.store_var(var, reg, value_type);
+      }
+      StmtKind::IndexAssign { var, index, expr } => {
+        let addr = self.gen_index_addr(&Expr::new(ExprKind::Var(var.clone()), stmt.span), index);
+        self.hold(addr);
+        let reg = self.gen_expr(expr);
+        let addr = self.unhold();
+        self.output.push(format!("  sw {}, 4({}) # Store element of {}", reg, addr, var));
         self.free_reg(reg);
+        self.free_reg(addr);
       }
-      Stmt::Exit(code) => {
+      StmtKind::Exit(code) => {
         if let Some(expr) = code {
           let reg = self.gen_expr(expr);
           self.output.push(format!("  mv a0, {} # exit code", reg));
@@ -95,8 +421,8 @@ impl CodeGen {
         self.output.push("  li a7, 10 # Syscall 10: exit".to_string());
         self.output.push("  ecall".to_string());
       }
-      Stmt::Print { expr } => self.gen_print(expr, false),
-      Stmt::PrintLn { expr } => match expr {
+      StmtKind::Print { expr } => self.gen_print(expr, false),
+      StmtKind::PrintLn { expr } => match expr {
         Some(expr) => self.gen_print(expr, true),
         None => {
           self.output.push("  li a0, '\\n' # Load newline char".to_string());
@@ -104,68 +430,404 @@ impl CodeGen {
           self.output.push("  ecall".to_string());
         }
       },
-      Stmt::While { condition, body } => {
-        let while_count = self.while_counter;
-        let while_start = format!("W{}_start", while_count);
-        let while_end = format!("W{}_end", while_count);
-        self.while_counter += 1;
+      StmtKind::While { label, condition, body } => {
+        let (while_start, _, while_end) = self.loop_labels();
         self.output.push(format!("{}:", while_start));
-        let reg = self.gen_expr(condition);
-        self.output.push(format!("  beq {}, x0, {}", reg, while_end));
-        self.free_reg(reg);
-        for stmt in body {
-          self.gen_stmt(stmt);
-        }
+        self.gen_branch(condition, &while_end, false);
+        self.gen_loop_body(label, &while_start, &while_end, body);
         self.output.push(format!("  j {}", while_start));
         self.output.push(format!("{}:", while_end));
       }
-      Stmt::If { condition, then_body, else_body } => {
+      StmtKind::For { label, init, condition, update, body } => {
+        let (loop_start, loop_continue, loop_end) = self.loop_labels();
+        self.vars.push();
+        for stmt in init {
+          self.gen_stmt(stmt);
+        }
+        self.output.push(format!("{}:", loop_start));
+        if let Some(condition) = condition {
+          self.gen_branch(condition, &loop_end, false);
+        }
+        self.gen_loop_body(label, &loop_continue, &loop_end, body);
+        self.output.push(format!("{}:", loop_continue));
+        for stmt in update {
+          self.gen_stmt(stmt);
+        }
+        self.output.push(format!("  j {}", loop_start));
+        self.output.push(format!("{}:", loop_end));
+        self.pop_scope();
+      }
+      StmtKind::ForRange { label, var, start, end, step, body } => {
+        let (loop_start, loop_continue, loop_end) = self.loop_labels();
+        self.vars.push();
+        let reg = self.gen_expr(start);
+        self.declare_var(var, reg, Type::Int);
+        // The end is evaluated once, before the first iteration.
+        let end_slot = self.alloc_free_slot();
+        let reg = self.gen_expr(end);
+        self.output.push(format!("  sw {}, {}(sp) # Save range end", reg, end_slot));
+        self.free_reg(reg);
+
+        self.output.push(format!("{}:", loop_start));
+        let var_reg = self.gen_expr(&Expr::new(ExprKind::Var(var.clone()), stmt.span));
+        let end_reg = self.alloc_reg();
+        self.output.push(format!("  lw {}, {}(sp) # Load range end", end_reg, end_slot));
+        if *step > 0 {
+          self.output.push(format!(
+            "  bge {}, {}, {} # Stop once {} reaches the end",
+            var_reg, end_reg, loop_end, var
+          ));
+        } else {
+          self.output.push(format!(
+            "  bge {}, {}, {} # Stop once {} reaches the end",
+            end_reg, var_reg, loop_end, var
+          ));
+        }
+        self.free_reg(end_reg);
+        self.free_reg(var_reg);
+        self.gen_loop_body(label, &loop_continue, &loop_end, body);
+
+        self.output.push(format!("{}:", loop_continue));
+        let var_reg = self.gen_expr(&Expr::new(ExprKind::Var(var.clone()), stmt.span));
+        if (-2048..=2047).contains(step) {
+          self.output.push(format!("  addi {}, {}, {} # Step", var_reg, var_reg, step));
+        } else {
+          let step_reg = self.alloc_reg();
+          self.output.push(format!("  li {}, {} # Step", step_reg, step));
+          self.output.push(format!("  add {}, {}, {}", var_reg, var_reg, step_reg));
+          self.free_reg(step_reg);
+        }
+        self.store_var(var, var_reg, Type::Int);
+        self.output.push(format!("  j {}", loop_start));
+        self.output.push(format!("{}:", loop_end));
+        self.pop_scope();
+        self.free_slots.push(end_slot);
+      }
+      StmtKind::Block(body) => self.gen_block(body),
+      StmtKind::Break(label) => {
+        let target = self.loop_targets(label).end_label.clone();
+        self.output.push(format!("  j {} # break", target));
+      }
+      StmtKind::Continue(label) => {
+        let target = self.loop_targets(label).continue_label.clone();
+        self.output.push(format!("  j {} # continue", target));
+      }
+      StmtKind::If { branches, else_body } => {
         let if_count = self.if_counter;
-        let else_label = format!("IF{}_else", if_count);
         let end_label = format!("IF{}_end", if_count);
         self.if_counter += 1;
 
-        let reg = self.gen_expr(condition);
+        // The whole `else if` chain shares one end label; each failed condition falls through
+        // to the next one, then to the else block.
+        for (i, (condition, body)) in branches.iter().enumerate() {
+          let next_label = if i + 1 < branches.len() {
+            self.output.push("  # Jump to next condition if condition is false".to_string());
+            format!("IF{}_elif{}", if_count, i + 1)
+          } else if else_body.is_some() {
+            self.output.push("  # Jump to else branch if condition is false".to_string());
+            format!("IF{}_else", if_count)
+          } else {
+            self.output.push("  # Jump to end if condition is false".to_string());
+            end_label.clone()
+          };
+          self.gen_branch(condition, &next_label, false);
 
-        if else_body.is_some() {
-          self
-            .output
-            .push(format!("  beq {}, x0, {} # Jump to else branch if condition is false", reg, else_label));
-        } else {
-          self.output.push(format!("  beq {}, x0, {} # Jump to end if condition is false", reg, end_label));
+          self.gen_block(body);
+
+          if next_label != end_label {
+            self.output.push(format!("  j {} # Skip the remaining branches", end_label));
+            self.output.push(format!("{}:", next_label));
+          }
         }
-        self.free_reg(reg);
 
-        for stmt in then_body {
-          self.gen_stmt(stmt);
+        if let Some(else_body) = else_body {
+          self.gen_block(else_body);
         }
 
-        if else_body.is_some() {
-          self.output.push(format!("  j {} # Skip else block", end_label));
-          self.output.push(format!("{}:", else_label));
+        self.output.push(format!("{}:", end_label));
+      }
+      StmtKind::Match { value, arms, default } => {
+        let match_count = self.match_counter;
+        self.match_counter += 1;
+        let end_label = format!("M{}_end", match_count);
+        let default_label = match default {
+          Some(_) => format!("M{}_default", match_count),
+          None => end_label.clone(),
+        };
+        let arm_labels: Vec<String> = (0..arms.len()).map(|i| format!("M{}_arm{}", match_count, i)).collect();
 
-          for stmt in else_body.as_ref().unwrap() {
-            self.gen_stmt(stmt);
-          }
+        let reg = self.gen_expr(value);
+        match Self::jump_table_bounds(arms) {
+          Some(bounds) => self.gen_jump_table(match_count, &reg, bounds, arms, &arm_labels, &default_label),
+          None => self.gen_compare_chain(&reg, arms, &arm_labels, &default_label),
         }
+        self.free_reg(reg);
 
+        for (arm, label) in arms.iter().zip(&arm_labels) {
+          self.output.push(format!("{}:", label));
+          self.gen_block(&arm.body);
+          self.output.push(format!("  j {} # End of match arm", end_label));
+        }
+        if let Some(default) = default {
+          self.output.push(format!("{}:", default_label));
+          self.gen_block(default);
+        }
         self.output.push(format!("{}:", end_label));
       }
+      StmtKind::Return(value) => {
+        let fn_name = self.current_fn.clone().expect("Compiler: 'return' outside of a function");
+        match value {
+          Some(expr) => {
+            let reg = self.gen_expr(expr);
+            self.output.push(format!("  mv a0, {} # Return value", reg));
+            self.free_reg(reg);
+          }
+          None => {
+            let ret = self.fn_types[&fn_name].clone();
+            self.gen_default_value(&ret);
+          }
+        }
+        self.output.push(format!("  j fn_{}_ret", fn_name));
+      }
+      StmtKind::Expr(expr) => {
+        let reg = self.gen_expr(expr);
+        self.free_reg(reg);
+      }
+      StmtKind::Function { .. } => panic!("Compiler: Functions can only be defined at the top level"),
+    }
+  }
+
+  /// Generates a `{}` block and hands the slots of its `let` variables back for reuse.
+  fn gen_block(&mut self, stmts: &[Stmt]) {
+    self.vars.push();
+    for stmt in stmts {
+      self.gen_stmt(stmt);
+    }
+    self.pop_scope();
+  }
+
+  fn pop_scope(&mut self) {
+    let ended = self.vars.pop();
+    self.free_slots.extend(ended.into_values().map(|var| var.offset));
+  }
+
+  /// Declares `var` in the innermost block and stores `reg` into it. A `let` that shadows a
+  /// variable of the same block takes over its slot, since the old one can no longer be read.
+  fn declare_var(&mut self, var: &str, reg: String, ty: Type) {
+    let offset = self.alloc_free_slot();
+    if let Some(old) = self.vars.declare(var, Var { offset, ty }) {
+      self.free_slots.push(old.offset);
+    }
+    self.output.push(format!("  sw {}, {}(sp) # Store variable {}", reg, offset, var));
+    self.free_reg(reg);
+  }
+
+  /// Stores `reg` into the slot of `var`, giving the variable a slot on its first assignment.
+  fn store_var(&mut self, var: &str, reg: String, ty: Type) {
+    let offset = match self.vars.get(var) {
+      // The parser keeps a variable's type fixed, so only a new one records `ty`.
+      Some(existing) => existing.offset,
+      None => {
+        // A new name belongs to the whole function, so its slot must not be one a block
+        // inside a loop hands back: the next iteration would overwrite it.
+        let offset = self.alloc_slot();
+        self.vars.assign(var, Var { offset, ty });
+        offset
+      }
+    };
+    self.output.push(format!("  sw {}, {}(sp) # Store variable {}", reg, offset, var));
+    self.free_reg(reg);
+  }
+
+  /// The smallest and largest matched values if a `match` should dispatch through a jump table:
+  /// it has enough values, and they fill at least half of a table that is not too long.
+  fn jump_table_bounds(arms: &[MatchArm]) -> Option<(i32, i32)> {
+    let ranges = || arms.iter().flat_map(|arm| &arm.ranges);
+    let min = ranges().map(|r| r.0).min()?;
+    let max = ranges().map(|r| r.1).max()?;
+    let len = max as i64 - min as i64 + 1;
+    let values: i64 = ranges().map(|&(lo, hi)| hi as i64 - lo as i64 + 1).sum();
+    let dense = values >= JUMP_TABLE_MIN_VALUES && len <= JUMP_TABLE_MAX_LEN && len <= 2 * values;
+    dense.then_some((min, max))
+  }
+
+  /// Jumps to the arm whose patterns hold the value in `reg` by testing them in order.
+  fn gen_compare_chain(&mut self, reg: &str, arms: &[MatchArm], arm_labels: &[String], default_label: &str) {
+    let tmp = self.alloc_reg();
+    for (arm, label) in arms.iter().zip(arm_labels) {
+      for &(lo, hi) in &arm.ranges {
+        if lo == hi {
+          self.output.push(format!("  li {}, {} # Match value {}", tmp, lo, lo));
+          self.output.push(format!("  beq {}, {}, {}", reg, tmp, label));
+        } else {
+          self.output.push(format!("  li {}, {} # Match range {}..={}", tmp, lo, lo, hi));
+          // `lo <= value <= hi` as one unsigned test: `value - lo` wraps past `hi - lo` when
+          // the value is below `lo`.
+          let span = self.alloc_reg();
+          self.output.push(format!("  sub {}, {}, {} # Offset into the range", tmp, reg, tmp));
+          self.output.push(format!("  li {}, {}", span, hi.wrapping_sub(lo)));
+          self.output.push(format!("  bleu {}, {}, {}", tmp, span, label));
+          self.free_reg(span);
+        }
+      }
+    }
+    self.free_reg(tmp);
+    self.output.push(format!("  j {} # No pattern matched", default_label));
+  }
+
+  /// Jumps to the arm for the value in `reg` through a table in `.data` with one entry per
+  /// value from the smallest pattern to the largest.
+  fn gen_jump_table(
+    &mut self,
+    match_count: usize,
+    reg: &str,
+    (min, max): (i32, i32),
+    arms: &[MatchArm],
+    arm_labels: &[String],
+    default_label: &str,
+  ) {
+    let targets: Vec<String> = (min..=max)
+      .map(|value| match arms.iter().position(|arm| arm.matches(value)) {
+        Some(i) => arm_labels[i].clone(),
+        None => default_label.to_string(),
+      })
+      .collect();
+    let table = format!("M{}_table", match_count);
+
+    let tmp = self.alloc_reg();
+    self.output.push(format!("  li {}, {} # Smallest matched value", tmp, min));
+    self.output.push(format!("  sub {}, {}, {} # Table index", reg, reg, tmp));
+    // Below `min` the index wraps to a huge unsigned number, so one test covers both ends.
+    self.output.push(format!("  li {}, {} # Table length", tmp, targets.len()));
+    self.output.push(format!("  bgeu {}, {}, {}", reg, tmp, default_label));
+    self.output.push(format!("  slli {}, {}, 2 # Entries are words", reg, reg));
+    self.output.push(format!("  la {}, {}", tmp, table));
+    self.output.push(format!("  add {}, {}, {}", reg, reg, tmp));
+    self.output.push(format!("  lw {}, 0({}) # Load the arm's address", reg, reg));
+    self.output.push(format!("  jr {}", reg));
+    self.free_reg(tmp);
+    self.jump_tables.push((table, targets));
+  }
+
+  /// Labels for a new loop: its start, where `continue` jumps, and its end. Only `for`
+  /// loops need a separate continue label, since their update runs before the next check.
+  fn loop_labels(&mut self) -> (String, String, String) {
+    let while_count = self.while_counter;
+    self.while_counter += 1;
+    (format!("W{}_start", while_count), format!("W{}_continue", while_count), format!("W{}_end", while_count))
+  }
+
+  /// Generates the body of a loop, with `break` and `continue` inside jumping to `end_label`
+  /// and `continue_label`.
+  fn gen_loop_body(&mut self, label: &Option<String>, continue_label: &str, end_label: &str, body: &[Stmt]) {
+    self.loops.push(LoopTargets {
+      label: label.clone(),
+      continue_label: continue_label.to_string(),
+      end_label: end_label.to_string(),
+    });
+    self.gen_block(body);
+    self.loops.pop();
+  }
+
+  /// The loop a `break` or `continue` with `label` refers to: the one carrying that label, or
+  /// the innermost one if there is none.
+  fn loop_targets(&self, label: &Option<String>) -> &LoopTargets {
+    let target = match label {
+      Some(label) => self.loops.iter().rev().find(|l| l.label.as_ref() == Some(label)),
+      None => self.loops.last(),
+    };
+    target.expect("Compiler: 'break' or 'continue' outside of a loop")
+  }
+
+  /// Jumps to `target` when `cond` evaluates to `jump_if`, falling through otherwise.
+  /// Logical operators and comparisons branch on their operands directly, so no
+  /// 0/1 value is materialised and the right operand of `&&`/`||` is skipped when
+  /// the left one already decides the outcome.
+  fn gen_branch(&mut self, cond: &Expr, target: &str, jump_if: bool) {
+    match &cond.kind {
+      ExprKind::BinOp { op: op @ (BinOp::AND | BinOp::OR), left, right } => {
+        let is_and = matches!(op, BinOp::AND);
+        if is_and != jump_if {
+          // `a && b` is false if either is false; `a || b` is true if either is true.
+          self.gen_branch(left, target, jump_if);
+          self.gen_branch(right, target, jump_if);
+        } else {
+          let skip_label = format!("SC{}_skip", self.sc_counter);
+          self.sc_counter += 1;
+          self.gen_branch(left, &skip_label, !jump_if);
+          self.gen_branch(right, target, jump_if);
+          self.output.push(format!("{}:", skip_label));
+        }
+      }
+      ExprKind::UnaryOp { op: UnaryOp::Not, expr } => self.gen_branch(expr, target, !jump_if),
+      ExprKind::BinOp {
+        op: op @ (BinOp::LT | BinOp::LTE | BinOp::GT | BinOp::GTE | BinOp::Eq | BinOp::Neq),
+        left,
+        right,
+      } => {
+        let left_reg = self.gen_expr(left);
+        self.hold(left_reg);
+        let right_reg = self.gen_expr(right);
+        let left_reg = self.unhold();
+        let (left_reg, right_reg) = if self.infer_type(left) == Type::String {
+          self.gen_strcmp(left_reg, right_reg)
+        } else {
+          (left_reg, right_reg)
+        };
+        // `a > b` is `b < a` and `a <= b` is `b >= a`, so only blt/bge/beq/bne are needed.
+        let (instr, first, second) = match (op, jump_if) {
+          (BinOp::LT, true) | (BinOp::GTE, false) => ("blt", &left_reg, &right_reg),
+          (BinOp::LT, false) | (BinOp::GTE, true) => ("bge", &left_reg, &right_reg),
+          (BinOp::GT, true) | (BinOp::LTE, false) => ("blt", &right_reg, &left_reg),
+          (BinOp::GT, false) | (BinOp::LTE, true) => ("bge", &right_reg, &left_reg),
+          (BinOp::Eq, true) | (BinOp::Neq, false) => ("beq", &left_reg, &right_reg),
+          _ => ("bne", &left_reg, &right_reg),
+        };
+        self.output.push(format!("  {} {}, {}, {}", instr, first, second, target));
+        self.free_reg(left_reg);
+        self.free_reg(right_reg);
+      }
+      _ => {
+        let reg = self.gen_expr(cond);
+        let instr = if jump_if { "bne" } else { "beq" };
+        self.output.push(format!("  {} {}, x0, {}", instr, reg, target));
+        self.free_reg(reg);
+      }
     }
   }
 
   fn infer_type(&mut self, expr: &Expr) -> Type {
-    match expr {
-      Expr::Int(_) => Type::Int,
-      Expr::String(_) => Type::String,
-      Expr::Input => Type::Int,
-      Expr::Var(name) => self
-        .var_types
-        .get(name)
-        .cloned()
-        .unwrap_or_else(|| panic!("Compiler: Variable '{}' type not tracked", name)),
-      Expr::BinOp { .. } => Type::Int,
-      Expr::UnaryOp { .. } => Type::Int,
+    match &expr.kind {
+      ExprKind::Int(_) => Type::Int,
+      ExprKind::String(_) => Type::String,
+      ExprKind::Bool(_) => Type::Bool,
+      ExprKind::Char(_) | ExprKind::Getc => Type::Char,
+      ExprKind::Const { value, .. } => value.get_type(),
+      ExprKind::Input(_) => Type::Int,
+      ExprKind::InputStr(_) => Type::String,
+      ExprKind::Call { ret, .. } => ret.clone(),
+      ExprKind::Array(_) | ExprKind::ArrayNew(_) => Type::Array,
+      ExprKind::Index { .. } | ExprKind::Len(_) => Type::Int,
+      ExprKind::Var(name) => match self.vars.get(name) {
+        Some(var) => var.ty.clone(),
+        None => panic!("Compiler: Variable '{}' type not tracked", name),
+      },
+      ExprKind::BinOp { op: op @ (BinOp::Add | BinOp::Sub), left, right } => {
+        match (op, self.infer_type(left), self.infer_type(right)) {
+          (BinOp::Add, Type::String, _) | (BinOp::Add, _, Type::String) => Type::String,
+          (BinOp::Sub, Type::Char, Type::Char) => Type::Int,
+          (_, Type::Char, _) | (_, _, Type::Char) => Type::Char,
+          _ => Type::Int,
+        }
+      }
+      ExprKind::BinOp {
+        op: BinOp::LT | BinOp::LTE | BinOp::GT | BinOp::GTE | BinOp::Eq | BinOp::Neq | BinOp::AND | BinOp::OR,
+        ..
+      } => Type::Bool,
+      ExprKind::BinOp { .. } => Type::Int,
+      ExprKind::UnaryOp { op: UnaryOp::Not, .. } => Type::Bool,
+      ExprKind::UnaryOp { .. } => Type::Int,
+      ExprKind::Conditional { then_expr, .. } => self.infer_type(then_expr),
     }
   }
 
@@ -173,19 +835,39 @@ impl CodeGen {
     let expr_type = self.infer_type(expr);
     match expr_type {
       Type::String => {
-        if let Expr::String(s) = expr {
+        if let ExprKind::String(s) = &expr.kind {
           let label = self.ensure_string_label(s);
           self.output.push(format!("  la a0, {} # Load string {}", label, Self::escape_asciz(s)));
           self.output.push("  li a7, 4 # Syscall 4: print_string".to_string());
           self.output.push("  ecall".to_string());
-        } else if let Expr::Var(name) = expr {
+        } else {
           let reg = self.gen_expr(expr);
-          self.output.push(format!("  mv a0, {} # Load string from variable {}", reg, name));
+          if let ExprKind::Var(name) = &expr.kind {
+            self.output.push(format!("  mv a0, {} # Load string from variable {}", reg, name));
+          } else {
+            self.output.push(format!("  mv a0, {} # String to print", reg));
+          }
           self.output.push("  li a7, 4 # Syscall 4: print_string".to_string());
           self.output.push("  ecall".to_string());
           self.free_reg(reg);
         }
       }
+      Type::Array => panic!("Compiler: Cannot print an array"),
+      Type::Bool => {
+        let reg = self.gen_expr(expr);
+        let reg = self.gen_bool_text(reg);
+        self.output.push(format!("  mv a0, {} # Boolean text to print", reg));
+        self.output.push("  li a7, 4 # Syscall 4: print_string".to_string());
+        self.output.push("  ecall".to_string());
+        self.free_reg(reg);
+      }
+      Type::Char => {
+        let reg = self.gen_expr(expr);
+        self.output.push(format!("  mv a0, {} # Character to print", reg));
+        self.output.push("  li a7, 11 # Syscall 11: print_character".to_string());
+        self.output.push("  ecall".to_string());
+        self.free_reg(reg);
+      }
       Type::Int => {
         let reg = self.gen_expr(expr);
         self.output.push(format!("  mv a0, {} # Expression to print", reg));
@@ -228,9 +910,12 @@ impl CodeGen {
         '\t' => escaped.push_str("\\t"),
         '\r' => escaped.push_str("\\r"),
         c if c.is_ascii_graphic() || c == ' ' => escaped.push(c),
+        // Anything else, including non-ASCII text, is written byte by byte in its UTF-8 form.
         c => {
           use std::fmt::Write;
-          write!(&mut escaped, "\\\\x{:02X}", c as u32).unwrap();
+          for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+            write!(&mut escaped, "\\x{:02X}", byte).unwrap();
+          }
         }
       }
     }
@@ -239,14 +924,37 @@ impl CodeGen {
   }
 
   fn gen_expr(&mut self, expr: &Expr) -> String {
-    match expr {
-      Expr::Int(n) => {
+    match &expr.kind {
+      ExprKind::Int(n) => {
         let reg = self.alloc_reg();
         self.output.push(format!("  li {}, {} # Load immediate {}", reg, n, n));
         reg
       }
-      Expr::Var(var) => {
-        if let Some(&offset) = self.vars.get(var) {
+      ExprKind::Bool(b) => {
+        let reg = self.alloc_reg();
+        self.output.push(format!("  li {}, {} # Load {}", reg, *b as i32, b));
+        reg
+      }
+      ExprKind::Char(c) => {
+        let reg = self.alloc_reg();
+        self.output.push(format!("  li {}, {} # Load char {:?}", reg, c, *c as char));
+        reg
+      }
+      ExprKind::Const { name, value } => {
+        let reg = self.alloc_reg();
+        match value {
+          ConstValue::Int(n) => self.output.push(format!("  li {}, {} # Constant {}", reg, n, name)),
+          ConstValue::Bool(b) => self.output.push(format!("  li {}, {} # Constant {}", reg, *b as i32, name)),
+          ConstValue::Char(c) => self.output.push(format!("  li {}, {} # Constant {}", reg, c, name)),
+          ConstValue::String(s) => {
+            let label = self.ensure_string_label(s);
+            self.output.push(format!("  la {}, {} # Constant {}", reg, label, name));
+          }
+        }
+        reg
+      }
+      ExprKind::Var(var) => {
+        if let Some(&Var { offset, .. }) = self.vars.get(var) {
           let reg = self.alloc_reg();
           self.output.push(format!("  lw {}, {}(sp) # Load variable {}", reg, offset, var));
           reg
@@ -254,9 +962,63 @@ impl CodeGen {
           panic!("Compiler: Variable '{}' not stored", var);
         }
       }
-      Expr::BinOp { op, left, right } => {
+      ExprKind::BinOp { op: BinOp::AND | BinOp::OR, .. } => {
+        let sc_count = self.sc_counter;
+        let false_label = format!("SC{}_false", sc_count);
+        let end_label = format!("SC{}_end", sc_count);
+        self.sc_counter += 1;
+
+        // The right operand only runs on some paths, so nothing may be spilled inside it.
+        self.spill_all_held();
+        self.gen_branch(expr, &false_label, false);
+        let result_reg = self.alloc_reg();
+        self.output.push(format!("  li {}, 1 # Logical result: true", result_reg));
+        self.output.push(format!("  j {}", end_label));
+        self.output.push(format!("{}:", false_label));
+        self.output.push(format!("  li {}, 0 # Logical result: false", result_reg));
+        self.output.push(format!("{}:", end_label));
+
+        result_reg
+      }
+      ExprKind::Conditional { condition, then_expr, else_expr } => {
+        let cond_count = self.cond_counter;
+        let else_label = format!("COND{}_else", cond_count);
+        let end_label = format!("COND{}_end", cond_count);
+        self.cond_counter += 1;
+
+        // Only one branch runs, so nothing may be spilled inside either of them.
+        self.spill_all_held();
+        self.gen_branch(condition, &else_label, false);
+        let result_reg = self.gen_expr(then_expr);
+        self.output.push(format!("  j {}", end_label));
+        self.output.push(format!("{}:", else_label));
+        // The else branch starts from the registers the then branch had, and leaves its value in
+        // the same register.
+        self.free_reg(result_reg.clone());
+        let else_reg = self.gen_expr(else_expr);
+        if else_reg != result_reg {
+          self.output.push(format!("  mv {}, {} # Conditional result", result_reg, else_reg));
+          self.free_reg(else_reg);
+          self.reg_pool.retain(|reg| *reg != result_reg);
+        }
+        self.output.push(format!("{}:", end_label));
+
+        result_reg
+      }
+      ExprKind::BinOp { op: BinOp::Add, left, right } if self.infer_type(expr) == Type::String => {
+        self.gen_concat(left, right)
+      }
+      ExprKind::BinOp { op, left, right } => {
         let left_reg = self.gen_expr(left);
+        self.hold(left_reg);
         let right_reg = self.gen_expr(right);
+        let left_reg = self.unhold();
+        // Only comparisons reach here with string operands.
+        let (left_reg, right_reg) = if self.infer_type(left) == Type::String {
+          self.gen_strcmp(left_reg, right_reg)
+        } else {
+          (left_reg, right_reg)
+        };
         let result_reg = self.alloc_reg();
 
         match op {
@@ -307,14 +1069,7 @@ impl CodeGen {
             self.output.push(format!("  sltu {}, x0, {} # diff != 0", result_reg, result_reg));
             self.output.push(format!("  sltu {}, x0, {} # Normalize result", result_reg, result_reg));
           }
-          BinOp::AND => {
-            self.output.push(format!("  and {}, {}, {} # Logical and", result_reg, left_reg, right_reg));
-            self.output.push(format!("  sltu {}, x0, {} # Normalize result", result_reg, result_reg));
-          }
-          BinOp::OR => {
-            self.output.push(format!("  or {}, {}, {} # Logical or", result_reg, left_reg, right_reg));
-            self.output.push(format!("  sltu {}, x0, {} # Normalize result", result_reg, result_reg));
-          }
+          BinOp::AND | BinOp::OR => unreachable!("Compiler: short-circuit operators are lowered separately"),
           BinOp::BitAnd => {
             self.output.push(format!("  and {}, {}, {} # Bitwise and", result_reg, left_reg, right_reg));
           }
@@ -338,20 +1093,23 @@ impl CodeGen {
             self.output.push(format!("  rem {}, {}, {} # Modulo operation", result_reg, left_reg, right_reg));
           }
         }
+        if self.infer_type(expr) == Type::Char {
+          self.output.push(format!("  andi {}, {}, 255 # Wrap to a byte", result_reg, result_reg));
+        }
 
         self.free_reg(left_reg);
         self.free_reg(right_reg);
 
         result_reg
       }
-      Expr::String(s) => {
+      ExprKind::String(s) => {
         let reg = self.alloc_reg();
         let label = self.ensure_string_label(s);
         self.output.push(format!("  la {}, {} # Store string {:?}", reg, label, Self::escape_asciz(s)));
 
         reg
       }
-      Expr::UnaryOp { op, expr } => {
+      ExprKind::UnaryOp { op, expr } => {
         let reg = self.gen_expr(expr);
         match op {
           UnaryOp::Not => {
@@ -367,13 +1125,227 @@ impl CodeGen {
 
         reg
       }
-      Expr::Input => {
+      ExprKind::Input(prompt) => {
+        if let Some(prompt) = prompt {
+          self.gen_print(prompt, false);
+        }
         let reg = self.alloc_reg();
         self.output.push("  li a7, 5 # Syscall 5: read_int".to_string());
         self.output.push("  ecall".to_string());
         self.output.push(format!("  mv {}, a0 # Store input result", reg));
         reg
       }
+      ExprKind::InputStr(max_len) => {
+        let reg = match max_len {
+          Some(max_len) => self.gen_expr(max_len),
+          None => {
+            let reg = self.alloc_reg();
+            self.output.push(format!("  li {}, {} # Default maximum length", reg, DEFAULT_INPUT_LEN));
+            reg
+          }
+        };
+        self.gen_routine_call(Routine::ReadStr, vec![reg])
+      }
+      ExprKind::Getc => {
+        let reg = self.alloc_reg();
+        self.output.push("  li a7, 12 # Syscall 12: read_char".to_string());
+        self.output.push("  ecall".to_string());
+        self.output.push(format!("  mv {}, a0 # Store character read", reg));
+        reg
+      }
+      ExprKind::Call { name, args, .. } => self.gen_call(name, args),
+      ExprKind::Array(elems) => {
+        let base = self.gen_array_storage(elems.len() as i32);
+        let len_reg = self.alloc_reg();
+        self.output.push(format!("  li {}, {} # Array length", len_reg, elems.len()));
+        self.output.push(format!("  sw {}, 0({}) # Store array length", len_reg, base));
+        self.free_reg(len_reg);
+        let mut base = base;
+        for (i, elem) in elems.iter().enumerate() {
+          self.hold(base);
+          let reg = self.gen_expr(elem);
+          base = self.unhold();
+          self.output.push(format!("  sw {}, {}({}) # Element {}", reg, 4 * (i + 1), base, i));
+          self.free_reg(reg);
+        }
+        base
+      }
+      ExprKind::ArrayNew(size) => {
+        // The heap is never reused, so fresh storage is already zeroed.
+        let base = self.gen_array_storage(*size);
+        let len_reg = self.alloc_reg();
+        self.output.push(format!("  li {}, {} # Array length", len_reg, size));
+        self.output.push(format!("  sw {}, 0({}) # Store array length", len_reg, base));
+        self.free_reg(len_reg);
+        base
+      }
+      ExprKind::Index { array, index } => {
+        let addr = self.gen_index_addr(array, index);
+        self.output.push(format!("  lw {}, 4({}) # Load array element", addr, addr));
+        addr
+      }
+      ExprKind::Len(array) => {
+        let reg = self.gen_expr(array);
+        self.output.push(format!("  lw {}, 0({}) # Load array length", reg, reg));
+        reg
+      }
     }
   }
+
+  /// Concatenates two operands into a new heap string, converting an integer side to
+  /// its decimal text and a char side to a one-character string first.
+  fn gen_concat(&mut self, left: &Expr, right: &Expr) -> String {
+    let left_reg = self.gen_string_operand(left);
+    self.hold(left_reg);
+    let right_reg = self.gen_string_operand(right);
+    let left_reg = self.unhold();
+    self.gen_routine_call(Routine::Concat, vec![left_reg, right_reg])
+  }
+
+  fn gen_string_operand(&mut self, expr: &Expr) -> String {
+    let reg = self.gen_expr(expr);
+    match self.infer_type(expr) {
+      Type::Int => self.gen_routine_call(Routine::Itoa, vec![reg]),
+      Type::Char => self.gen_routine_call(Routine::Chr, vec![reg]),
+      Type::Bool => self.gen_bool_text(reg),
+      _ => reg,
+    }
+  }
+
+  /// Replaces the boolean in `reg` with the address of `"true"` or `"false"`.
+  fn gen_bool_text(&mut self, reg: String) -> String {
+    let bool_count = self.bool_counter;
+    let false_label = format!("BOOL{}_false", bool_count);
+    let end_label = format!("BOOL{}_end", bool_count);
+    self.bool_counter += 1;
+
+    let true_str = self.ensure_string_label(&"true".to_string());
+    let false_str = self.ensure_string_label(&"false".to_string());
+    self.output.push(format!("  beq {}, x0, {}", reg, false_label));
+    self.output.push(format!("  la {}, {} # \"true\"", reg, true_str));
+    self.output.push(format!("  j {}", end_label));
+    self.output.push(format!("{}:", false_label));
+    self.output.push(format!("  la {}, {} # \"false\"", reg, false_str));
+    self.output.push(format!("{}:", end_label));
+    reg
+  }
+
+  /// Compares two strings with `rt_strcmp`. Its result orders like `left - right`, so the
+  /// returned pair (result, `x0`) can be compared with the same instructions as integers.
+  fn gen_strcmp(&mut self, left_reg: String, right_reg: String) -> (String, String) {
+    let cmp = self.gen_routine_call(Routine::Strcmp, vec![left_reg, right_reg]);
+    (cmp, "x0".to_string())
+  }
+
+  /// Calls a runtime library routine with `args` in `a0` upwards and returns a register
+  /// holding its result. Routines only clobber `a0-a7`, so no temporaries are saved.
+  fn gen_routine_call(&mut self, routine: Routine, args: Vec<String>) -> String {
+    self.use_routine(routine);
+    for (arg, arg_reg) in args.iter().zip(ARG_REGS) {
+      self.output.push(format!("  mv {}, {}", arg_reg, arg));
+    }
+    for arg in args {
+      self.free_reg(arg);
+    }
+    self.output.push(format!("  jal ra, {}", routine.label()));
+    let reg = self.alloc_reg();
+    self.output.push(format!("  mv {}, a0 # Result of {}", reg, routine.label()));
+    reg
+  }
+
+  fn use_routine(&mut self, routine: Routine) {
+    if self.routines.insert(routine) {
+      for &dep in routine.deps() {
+        self.use_routine(dep);
+      }
+    }
+  }
+
+  /// Allocates storage for an array of `len` integers plus its length header on the heap and
+  /// returns a register holding its base address. Every evaluation gets a new array, so one
+  /// created in a loop or a recursive call never overwrites an earlier one still referenced.
+  fn gen_array_storage(&mut self, len: i32) -> String {
+    // The length header takes one more word.
+    let size = len.checked_add(1).and_then(|words| words.checked_mul(4)).unwrap_or_else(|| {
+      self.error.get_or_insert(CompileError::CodegenError {
+        msg: format!("Array of {} elements does not fit in memory", len),
+      });
+      0
+    });
+    self.output.push(format!("  li a0, {} # Array size in bytes", size));
+    self.output.push("  li a7, 9 # Syscall 9: sbrk".to_string());
+    self.output.push("  ecall".to_string());
+    let reg = self.alloc_reg();
+    self.output.push(format!("  mv {}, a0 # Array storage", reg));
+    reg
+  }
+
+  /// Returns a register holding the address of `array[index]` minus 4, so the
+  /// element itself is at `4(reg)`, just past the length header.
+  fn gen_index_addr(&mut self, array: &Expr, index: &Expr) -> String {
+    let base = self.gen_expr(array);
+    self.hold(base);
+    let idx = self.gen_expr(index);
+    let base = self.unhold();
+
+    if self.bounds_check {
+      let ok_label = format!("BC{}_ok", self.bounds_counter);
+      self.bounds_counter += 1;
+      self.uses_bounds_fail = true;
+
+      let len = self.alloc_reg();
+      self.output.push(format!("  lw {}, 0({}) # Load array length", len, base));
+      self.output.push(format!(
+        "  bltu {}, {}, {} # Unsigned compare also rejects negative indices",
+        idx, len, ok_label
+      ));
+      self.output.push("  j rt_bounds_fail".to_string());
+      self.output.push(format!("{}:", ok_label));
+      self.free_reg(len);
+    }
+
+    self.output.push(format!("  slli {}, {}, 2 # Index in bytes", idx, idx));
+    self.output.push(format!("  add {}, {}, {} # Element address", idx, idx, base));
+    self.free_reg(base);
+    idx
+  }
+
+  fn gen_call(&mut self, name: &str, args: &[Expr]) -> String {
+    // Arguments are parked in the frame while the rest are evaluated, since a
+    // nested call would clobber a0-a7.
+    let depth = self.call_depth;
+    self.call_depth += 1;
+    for (i, arg) in args.iter().enumerate() {
+      let reg = self.gen_expr(arg);
+      let slot = self.arg_slot(depth, i);
+      self.output.push(format!("  sw {}, {}(sp) # Argument {} of {}", reg, slot, i, name));
+      self.free_reg(reg);
+    }
+    self.call_depth -= 1;
+
+    let live: Vec<String> = TEMP_REGS[..self.reg_count]
+      .iter()
+      .filter(|r| !self.reg_pool.iter().any(|p| p == *r))
+      .map(|r| r.to_string())
+      .collect();
+    for (i, reg) in live.iter().enumerate() {
+      let slot = self.save_slot(i);
+      self.output.push(format!("  sw {}, {}(sp) # Save {} across call", reg, slot, reg));
+    }
+
+    for (i, arg_reg) in ARG_REGS.iter().enumerate().take(args.len()) {
+      let slot = self.arg_slot(depth, i);
+      self.output.push(format!("  lw {}, {}(sp) # Load argument {}", arg_reg, slot, i));
+    }
+    self.output.push(format!("  jal ra, fn_{} # Call {}", name, name));
+
+    for (i, reg) in live.iter().enumerate() {
+      let slot = self.save_slot(i);
+      self.output.push(format!("  lw {}, {}(sp) # Restore {}", reg, slot, reg));
+    }
+
+    let reg = self.alloc_reg();
+    self.output.push(format!("  mv {}, a0 # Return value of {}", reg, name));
+    reg
+  }
 }
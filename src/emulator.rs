@@ -0,0 +1,1122 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::error::CompileError;
+
+/// Segment layout, matching the RARS defaults so addresses look familiar when debugging.
+const TEXT_BASE: u32 = 0x0040_0000;
+const DATA_BASE: u32 = 0x1001_0000;
+/// Where `sbrk` starts handing out memory, unless `.data` extends past it.
+const HEAP_BASE: u32 = 0x1004_0000;
+const GLOBAL_POINTER: u32 = 0x1000_8000;
+const STACK_POINTER: u32 = 0x7fff_effc;
+
+const PAGE_SIZE: u32 = 4096;
+
+#[derive(Debug, Clone, Copy)]
+enum AluOp {
+  Add,
+  Sub,
+  Sll,
+  Slt,
+  Sltu,
+  Xor,
+  Srl,
+  Sra,
+  Or,
+  And,
+  Mul,
+  Mulh,
+  Mulhsu,
+  Mulhu,
+  Div,
+  Divu,
+  Rem,
+  Remu,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BranchOp {
+  Eq,
+  Ne,
+  Lt,
+  Ge,
+  Ltu,
+  Geu,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Width {
+  Byte,
+  ByteU,
+  Half,
+  HalfU,
+  Word,
+}
+
+/// A decoded instruction. Pseudo-instructions are expanded while assembling, so every entry
+/// here is one step of the machine and occupies 4 bytes of the text segment.
+#[derive(Debug, Clone, Copy)]
+enum Instr {
+  Op { op: AluOp, rd: usize, rs1: usize, rs2: usize },
+  OpImm { op: AluOp, rd: usize, rs1: usize, imm: i32 },
+  Load { width: Width, rd: usize, rs1: usize, offset: i32 },
+  Store { width: Width, rs1: usize, rs2: usize, offset: i32 },
+  Branch { op: BranchOp, rs1: usize, rs2: usize, target: u32 },
+  Jal { rd: usize, target: u32 },
+  Jalr { rd: usize, rs1: usize, offset: i32 },
+  Li { rd: usize, imm: i32 },
+  Ecall,
+}
+
+/// Sparse byte-addressed memory, allocated a page at a time on first write.
+#[derive(Default)]
+struct Memory {
+  pages: HashMap<u32, Box<[u8; PAGE_SIZE as usize]>>,
+}
+
+impl Memory {
+  fn load_byte(&self, addr: u32) -> u8 {
+    self.pages.get(&(addr / PAGE_SIZE)).map_or(0, |page| page[(addr % PAGE_SIZE) as usize])
+  }
+
+  fn store_byte(&mut self, addr: u32, value: u8) {
+    let page = self.pages.entry(addr / PAGE_SIZE).or_insert_with(|| Box::new([0; PAGE_SIZE as usize]));
+    page[(addr % PAGE_SIZE) as usize] = value;
+  }
+
+  fn load(&self, addr: u32, bytes: u32) -> u32 {
+    (0..bytes).fold(0, |acc, i| acc | (self.load_byte(addr.wrapping_add(i)) as u32) << (8 * i))
+  }
+
+  fn store(&mut self, addr: u32, bytes: u32, value: u32) {
+    for i in 0..bytes {
+      self.store_byte(addr.wrapping_add(i), (value >> (8 * i)) as u8);
+    }
+  }
+}
+
+/// An RV32IM machine that runs the assembly produced by `CodeGen::generate`, so programs can be
+/// executed without RARS. Only the RARS syscalls the generator emits are supported.
+pub struct Emulator {
+  text: Vec<Instr>,
+  /// Source line of each instruction in the assembly, for runtime error messages.
+  lines: Vec<usize>,
+  entry: u32,
+  regs: [u32; 32],
+  pc: u32,
+  memory: Memory,
+  /// Next address `sbrk` hands out.
+  heap: u32,
+}
+
+impl Emulator {
+  /// Assembles `asm` and loads it into a fresh machine.
+  pub fn new(asm: &str) -> Result<Self, CompileError> {
+    let program = Assembler::default().assemble(asm)?;
+    let mut memory = Memory::default();
+    for (i, byte) in program.data.iter().enumerate() {
+      memory.store_byte(DATA_BASE + i as u32, *byte);
+    }
+    // A data segment too large for the RARS layout pushes the heap up past its end, so the two
+    // never overlap.
+    let data_end = (DATA_BASE + program.data.len() as u32).next_multiple_of(8);
+    Ok(Emulator {
+      text: program.text,
+      lines: program.lines,
+      entry: program.entry,
+      regs: [0; 32],
+      pc: program.entry,
+      memory,
+      heap: HEAP_BASE.max(data_end),
+    })
+  }
+
+  /// Runs the program from `main` until it exits, reading syscall input from `input` and writing
+  /// output to `output`. Returns the exit code.
+  pub fn run<R: BufRead, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<i32, CompileError> {
+    self.regs = [0; 32];
+    self.regs[2] = STACK_POINTER;
+    self.regs[3] = GLOBAL_POINTER;
+    self.pc = self.entry;
+
+    let result = self.execute(input, output);
+    output.flush().map_err(|e| self.runtime_error(format!("Failed to write output: {}", e)))?;
+    result
+  }
+
+  fn execute<R: BufRead, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<i32, CompileError> {
+    loop {
+      let index = self.pc.wrapping_sub(TEXT_BASE) / 4;
+      if self.pc < TEXT_BASE || !self.pc.is_multiple_of(4) || index as usize >= self.text.len() {
+        return Err(CompileError::RuntimeError {
+          msg: format!("Jumped to 0x{:08x}, which is outside the program", self.pc),
+        });
+      }
+      let mut next_pc = self.pc.wrapping_add(4);
+
+      match self.text[index as usize] {
+        Instr::Op { op, rd, rs1, rs2 } => {
+          let value = alu(op, self.regs[rs1], self.regs[rs2]);
+          self.write_reg(rd, value);
+        }
+        Instr::OpImm { op, rd, rs1, imm } => {
+          let value = alu(op, self.regs[rs1], imm as u32);
+          self.write_reg(rd, value);
+        }
+        Instr::Load { width, rd, rs1, offset } => {
+          let addr = self.regs[rs1].wrapping_add(offset as u32);
+          let value = match width {
+            Width::Byte => self.memory.load(addr, 1) as i8 as i32 as u32,
+            Width::ByteU => self.memory.load(addr, 1),
+            Width::Half => self.load_aligned(addr, 2)? as i16 as i32 as u32,
+            Width::HalfU => self.load_aligned(addr, 2)?,
+            Width::Word => self.load_aligned(addr, 4)?,
+          };
+          self.write_reg(rd, value);
+        }
+        Instr::Store { width, rs1, rs2, offset } => {
+          let addr = self.regs[rs1].wrapping_add(offset as u32);
+          let bytes = match width {
+            Width::Byte | Width::ByteU => 1,
+            Width::Half | Width::HalfU => 2,
+            Width::Word => 4,
+          };
+          if !addr.is_multiple_of(bytes) {
+            return Err(self.runtime_error(format!("Misaligned store to 0x{:08x}", addr)));
+          }
+          self.memory.store(addr, bytes, self.regs[rs2]);
+        }
+        Instr::Branch { op, rs1, rs2, target } => {
+          let (a, b) = (self.regs[rs1], self.regs[rs2]);
+          let taken = match op {
+            BranchOp::Eq => a == b,
+            BranchOp::Ne => a != b,
+            BranchOp::Lt => (a as i32) < (b as i32),
+            BranchOp::Ge => (a as i32) >= (b as i32),
+            BranchOp::Ltu => a < b,
+            BranchOp::Geu => a >= b,
+          };
+          if taken {
+            next_pc = target;
+          }
+        }
+        Instr::Jal { rd, target } => {
+          self.write_reg(rd, next_pc);
+          next_pc = target;
+        }
+        Instr::Jalr { rd, rs1, offset } => {
+          let target = self.regs[rs1].wrapping_add(offset as u32) & !1;
+          self.write_reg(rd, next_pc);
+          next_pc = target;
+        }
+        Instr::Li { rd, imm } => self.write_reg(rd, imm as u32),
+        Instr::Ecall => {
+          if let Some(code) = self.syscall(input, output)? {
+            return Ok(code);
+          }
+        }
+      }
+
+      self.pc = next_pc;
+    }
+  }
+
+  /// Performs the syscall selected by `a7`. Returns the exit code once the program exits.
+  fn syscall<R: BufRead, W: Write>(
+    &mut self,
+    input: &mut R,
+    output: &mut W,
+  ) -> Result<Option<i32>, CompileError> {
+    let a0 = self.regs[10];
+    let io_error = |e: std::io::Error| CompileError::RuntimeError { msg: format!("I/O error: {}", e) };
+
+    match self.regs[17] {
+      // print_int
+      1 => write!(output, "{}", a0 as i32).map_err(io_error)?,
+      // print_string
+      4 => {
+        let mut bytes = Vec::new();
+        let mut addr = a0;
+        loop {
+          let byte = self.memory.load_byte(addr);
+          if byte == 0 {
+            break;
+          }
+          bytes.push(byte);
+          addr = addr.wrapping_add(1);
+        }
+        output.write_all(&bytes).map_err(io_error)?;
+      }
+      // read_int
+      5 => {
+        output.flush().map_err(io_error)?;
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(io_error)? == 0 {
+          return Err(self.runtime_error("Reached end of input while reading an integer".to_string()));
+        }
+        let value = line
+          .trim()
+          .parse::<i32>()
+          .map_err(|_| self.runtime_error(format!("Invalid integer input: '{}'", line.trim())))?;
+        self.write_reg(10, value as u32);
+      }
+      // read_string: at most a1 - 1 bytes of the next line, newline included if it fits. The rest
+      // of the line is discarded, as RARS does.
+      8 => {
+        output.flush().map_err(io_error)?;
+        let mut line = Vec::new();
+        if input.read_until(b'\n', &mut line).map_err(io_error)? == 0 {
+          return Err(self.runtime_error("Reached end of input while reading a string".to_string()));
+        }
+        line.truncate((self.regs[11] as i32).saturating_sub(1).max(0) as usize);
+        line.push(0);
+        for (i, byte) in line.iter().enumerate() {
+          self.memory.store_byte(a0.wrapping_add(i as u32), *byte);
+        }
+      }
+      // sbrk: a0 bytes of zeroed heap, rounded up to a whole word like RARS does
+      9 => {
+        let size = a0 as i32;
+        if size < 0 {
+          return Err(self.runtime_error(format!("sbrk with negative size {}", size)));
+        }
+        let addr = self.heap;
+        self.heap = addr.wrapping_add((size as u32).div_ceil(4) * 4);
+        self.write_reg(10, addr);
+      }
+      // exit. RARS always exits with 0 here; the generator puts the exit code in a0, so honor it.
+      10 => return Ok(Some(a0 as i32)),
+      // print_char
+      11 => output.write_all(&[a0 as u8]).map_err(io_error)?,
+      // read_char
+      12 => {
+        output.flush().map_err(io_error)?;
+        let Some(&byte) = input.fill_buf().map_err(io_error)?.first() else {
+          return Err(self.runtime_error("Reached end of input while reading a character".to_string()));
+        };
+        input.consume(1);
+        self.write_reg(10, byte as u32);
+      }
+      // exit2
+      93 => return Ok(Some(a0 as i32)),
+      other => return Err(self.runtime_error(format!("Unsupported syscall {}", other))),
+    }
+
+    Ok(None)
+  }
+
+  fn load_aligned(&self, addr: u32, bytes: u32) -> Result<u32, CompileError> {
+    if !addr.is_multiple_of(bytes) {
+      return Err(self.runtime_error(format!("Misaligned load from 0x{:08x}", addr)));
+    }
+    Ok(self.memory.load(addr, bytes))
+  }
+
+  fn write_reg(&mut self, rd: usize, value: u32) {
+    if rd != 0 {
+      self.regs[rd] = value;
+    }
+  }
+
+  fn runtime_error(&self, msg: String) -> CompileError {
+    let index = (self.pc.wrapping_sub(TEXT_BASE) / 4) as usize;
+    match self.lines.get(index) {
+      Some(line) => CompileError::RuntimeError { msg: format!("{} (assembly line {})", msg, line) },
+      None => CompileError::RuntimeError { msg },
+    }
+  }
+}
+
+fn alu(op: AluOp, a: u32, b: u32) -> u32 {
+  let (sa, sb) = (a as i32, b as i32);
+  match op {
+    AluOp::Add => a.wrapping_add(b),
+    AluOp::Sub => a.wrapping_sub(b),
+    AluOp::Sll => a << (b & 31),
+    AluOp::Slt => (sa < sb) as u32,
+    AluOp::Sltu => (a < b) as u32,
+    AluOp::Xor => a ^ b,
+    AluOp::Srl => a >> (b & 31),
+    AluOp::Sra => (sa >> (b & 31)) as u32,
+    AluOp::Or => a | b,
+    AluOp::And => a & b,
+    AluOp::Mul => a.wrapping_mul(b),
+    AluOp::Mulh => ((sa as i64 * sb as i64) >> 32) as u32,
+    AluOp::Mulhsu => ((sa as i64 * b as i64) >> 32) as u32,
+    AluOp::Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+    // Division never traps on RISC-V: dividing by zero gives -1 (or the dividend for the
+    // remainder), and i32::MIN / -1 overflows back to i32::MIN.
+    AluOp::Div => {
+      if sb == 0 {
+        u32::MAX
+      } else {
+        sa.wrapping_div(sb) as u32
+      }
+    }
+    AluOp::Divu => a.checked_div(b).unwrap_or(u32::MAX),
+    AluOp::Rem => {
+      if sb == 0 {
+        a
+      } else {
+        sa.wrapping_rem(sb) as u32
+      }
+    }
+    AluOp::Remu => a.checked_rem(b).unwrap_or(a),
+  }
+}
+
+struct Program {
+  text: Vec<Instr>,
+  lines: Vec<usize>,
+  data: Vec<u8>,
+  entry: u32,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Section {
+  Text,
+  Data,
+}
+
+/// A two-pass assembler: the first pass lays out `.data` and records label addresses, the
+/// second decodes instructions once every label is known.
+#[derive(Default)]
+struct Assembler {
+  labels: HashMap<String, u32>,
+  data: Vec<u8>,
+  /// `.word` entries that name a label, patched after the first pass.
+  word_fixups: Vec<(usize, usize, String)>,
+  /// Instructions as (line, mnemonic, operands), decoded in the second pass.
+  pending: Vec<(usize, String, Vec<String>)>,
+}
+
+impl Assembler {
+  fn assemble(mut self, asm: &str) -> Result<Program, CompileError> {
+    let mut section = Section::Text;
+
+    for (i, raw) in asm.lines().enumerate() {
+      let line = i + 1;
+      let mut rest = strip_comment(raw).trim();
+
+      while let Some((label, after)) = split_label(rest) {
+        let addr = match section {
+          Section::Text => TEXT_BASE + 4 * self.pending.len() as u32,
+          Section::Data => DATA_BASE + self.data.len() as u32,
+        };
+        if self.labels.insert(label.to_string(), addr).is_some() {
+          return Err(asm_error(line, format!("Label '{}' is defined more than once", label)));
+        }
+        rest = after.trim();
+      }
+
+      if rest.is_empty() {
+        continue;
+      }
+
+      let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+        Some(pos) => (&rest[..pos], rest[pos..].trim()),
+        None => (rest, ""),
+      };
+      let operands = split_operands(operands);
+
+      match mnemonic {
+        ".text" => section = Section::Text,
+        ".data" => section = Section::Data,
+        ".globl" | ".global" => {}
+        _ if mnemonic.starts_with('.') => {
+          if section != Section::Data {
+            return Err(asm_error(line, format!("Directive '{}' is only supported in .data", mnemonic)));
+          }
+          self.directive(line, mnemonic, &operands)?;
+        }
+        _ => {
+          if section != Section::Text {
+            return Err(asm_error(line, format!("Instruction '{}' outside of .text", mnemonic)));
+          }
+          self.pending.push((line, mnemonic.to_string(), operands));
+        }
+      }
+    }
+
+    for (line, offset, label) in std::mem::take(&mut self.word_fixups) {
+      let addr = self.label(line, &label)?;
+      self.data[offset..offset + 4].copy_from_slice(&addr.to_le_bytes());
+    }
+
+    let mut text = Vec::new();
+    let mut lines = Vec::new();
+    for (index, (line, mnemonic, operands)) in self.pending.iter().enumerate() {
+      let pc = TEXT_BASE + 4 * index as u32;
+      text.push(self.instruction(*line, pc, mnemonic, operands)?);
+      lines.push(*line);
+    }
+
+    let entry = self.labels.get("main").copied().unwrap_or(TEXT_BASE);
+    Ok(Program { text, lines, data: self.data, entry })
+  }
+
+  fn directive(&mut self, line: usize, name: &str, operands: &[String]) -> Result<(), CompileError> {
+    match name {
+      ".asciz" | ".string" | ".ascii" => {
+        for operand in operands {
+          self.data.extend(parse_string(line, operand)?);
+          if name != ".ascii" {
+            self.data.push(0);
+          }
+        }
+      }
+      ".space" => {
+        let size = parse_imm(line, single(line, name, operands)?)?;
+        if size < 0 {
+          return Err(asm_error(line, ".space size cannot be negative".to_string()));
+        }
+        self.data.resize(self.data.len() + size as usize, 0);
+      }
+      ".align" => {
+        let power = parse_imm(line, single(line, name, operands)?)?;
+        if !(0..=12).contains(&power) {
+          return Err(asm_error(line, format!(".align {} is out of range", power)));
+        }
+        self.align(1 << power);
+      }
+      ".word" => {
+        self.align(4);
+        for operand in operands {
+          let offset = self.data.len();
+          match parse_imm(line, operand) {
+            Ok(value) => self.data.extend(value.to_le_bytes()),
+            Err(_) => {
+              self.word_fixups.push((line, offset, operand.clone()));
+              self.data.extend([0; 4]);
+            }
+          }
+        }
+      }
+      ".half" => {
+        self.align(2);
+        for operand in operands {
+          self.data.extend((parse_imm(line, operand)? as u16).to_le_bytes());
+        }
+      }
+      ".byte" => {
+        for operand in operands {
+          self.data.push(parse_imm(line, operand)? as u8);
+        }
+      }
+      _ => return Err(asm_error(line, format!("Unsupported directive '{}'", name))),
+    }
+    Ok(())
+  }
+
+  fn align(&mut self, to: usize) {
+    let padded = self.data.len().div_ceil(to) * to;
+    self.data.resize(padded, 0);
+  }
+
+  fn label(&self, line: usize, name: &str) -> Result<u32, CompileError> {
+    self.labels.get(name).copied().ok_or_else(|| asm_error(line, format!("Undefined label '{}'", name)))
+  }
+
+  fn instruction(&self, line: usize, pc: u32, mnemonic: &str, ops: &[String]) -> Result<Instr, CompileError> {
+    let arity = |n: usize| -> Result<(), CompileError> {
+      if ops.len() == n {
+        Ok(())
+      } else {
+        Err(asm_error(line, format!("'{}' expects {} operand(s), found {}", mnemonic, n, ops.len())))
+      }
+    };
+    let reg = |i: usize| parse_reg(line, &ops[i]);
+    let imm12 = |i: usize| parse_imm12(line, &ops[i]);
+    let target = |i: usize| self.label(line, &ops[i]);
+
+    if let Some(op) = alu_op(mnemonic) {
+      arity(3)?;
+      return Ok(Instr::Op { op, rd: reg(0)?, rs1: reg(1)?, rs2: reg(2)? });
+    }
+    if let Some(op) = alu_imm_op(mnemonic) {
+      arity(3)?;
+      let imm = match op {
+        AluOp::Sll | AluOp::Srl | AluOp::Sra => {
+          let shamt = parse_imm(line, &ops[2])?;
+          if !(0..32).contains(&shamt) {
+            return Err(asm_error(line, format!("Shift amount {} is out of range", shamt)));
+          }
+          shamt
+        }
+        _ => imm12(2)?,
+      };
+      return Ok(Instr::OpImm { op, rd: reg(0)?, rs1: reg(1)?, imm });
+    }
+    if let Some(width) = load_width(mnemonic) {
+      arity(2)?;
+      let (offset, rs1) = parse_mem(line, &ops[1])?;
+      return Ok(Instr::Load { width, rd: reg(0)?, rs1, offset });
+    }
+    if let Some(width) = store_width(mnemonic) {
+      arity(2)?;
+      let (offset, rs1) = parse_mem(line, &ops[1])?;
+      return Ok(Instr::Store { width, rs1, rs2: reg(0)?, offset });
+    }
+    if let Some((op, swap)) = branch_op(mnemonic) {
+      arity(3)?;
+      let (rs1, rs2) = if swap { (reg(1)?, reg(0)?) } else { (reg(0)?, reg(1)?) };
+      return Ok(Instr::Branch { op, rs1, rs2, target: target(2)? });
+    }
+
+    let instr = match mnemonic {
+      "beqz" | "bnez" | "bltz" | "bgez" | "blez" | "bgtz" => {
+        arity(2)?;
+        let rs = reg(0)?;
+        let (op, rs1, rs2) = match mnemonic {
+          "beqz" => (BranchOp::Eq, rs, 0),
+          "bnez" => (BranchOp::Ne, rs, 0),
+          "bltz" => (BranchOp::Lt, rs, 0),
+          "bgez" => (BranchOp::Ge, rs, 0),
+          "blez" => (BranchOp::Ge, 0, rs),
+          _ => (BranchOp::Lt, 0, rs),
+        };
+        Instr::Branch { op, rs1, rs2, target: target(1)? }
+      }
+      "jal" => match ops.len() {
+        1 => Instr::Jal { rd: 1, target: target(0)? },
+        _ => {
+          arity(2)?;
+          Instr::Jal { rd: reg(0)?, target: target(1)? }
+        }
+      },
+      "jalr" => match ops.len() {
+        1 => Instr::Jalr { rd: 1, rs1: reg(0)?, offset: 0 },
+        2 => {
+          let (offset, rs1) = parse_mem(line, &ops[1])?;
+          Instr::Jalr { rd: reg(0)?, rs1, offset }
+        }
+        _ => {
+          arity(3)?;
+          Instr::Jalr { rd: reg(0)?, rs1: reg(1)?, offset: imm12(2)? }
+        }
+      },
+      "j" => {
+        arity(1)?;
+        Instr::Jal { rd: 0, target: target(0)? }
+      }
+      "call" => {
+        arity(1)?;
+        Instr::Jal { rd: 1, target: target(0)? }
+      }
+      "jr" => {
+        arity(1)?;
+        Instr::Jalr { rd: 0, rs1: reg(0)?, offset: 0 }
+      }
+      "ret" => {
+        arity(0)?;
+        Instr::Jalr { rd: 0, rs1: 1, offset: 0 }
+      }
+      "li" => {
+        arity(2)?;
+        Instr::Li { rd: reg(0)?, imm: parse_imm(line, &ops[1])? }
+      }
+      "la" => {
+        arity(2)?;
+        Instr::Li { rd: reg(0)?, imm: target(1)? as i32 }
+      }
+      "lui" => {
+        arity(2)?;
+        Instr::Li { rd: reg(0)?, imm: parse_upper(line, &ops[1])? << 12 }
+      }
+      "auipc" => {
+        arity(2)?;
+        Instr::Li { rd: reg(0)?, imm: pc.wrapping_add((parse_upper(line, &ops[1])? << 12) as u32) as i32 }
+      }
+      "mv" => {
+        arity(2)?;
+        Instr::OpImm { op: AluOp::Add, rd: reg(0)?, rs1: reg(1)?, imm: 0 }
+      }
+      "not" => {
+        arity(2)?;
+        Instr::OpImm { op: AluOp::Xor, rd: reg(0)?, rs1: reg(1)?, imm: -1 }
+      }
+      "neg" => {
+        arity(2)?;
+        Instr::Op { op: AluOp::Sub, rd: reg(0)?, rs1: 0, rs2: reg(1)? }
+      }
+      "seqz" => {
+        arity(2)?;
+        Instr::OpImm { op: AluOp::Sltu, rd: reg(0)?, rs1: reg(1)?, imm: 1 }
+      }
+      "snez" => {
+        arity(2)?;
+        Instr::Op { op: AluOp::Sltu, rd: reg(0)?, rs1: 0, rs2: reg(1)? }
+      }
+      "sltz" => {
+        arity(2)?;
+        Instr::Op { op: AluOp::Slt, rd: reg(0)?, rs1: reg(1)?, rs2: 0 }
+      }
+      "sgtz" => {
+        arity(2)?;
+        Instr::Op { op: AluOp::Slt, rd: reg(0)?, rs1: 0, rs2: reg(1)? }
+      }
+      "nop" => {
+        arity(0)?;
+        Instr::OpImm { op: AluOp::Add, rd: 0, rs1: 0, imm: 0 }
+      }
+      "ecall" => {
+        arity(0)?;
+        Instr::Ecall
+      }
+      _ => return Err(asm_error(line, format!("Unknown instruction '{}'", mnemonic))),
+    };
+    Ok(instr)
+  }
+}
+
+fn alu_op(mnemonic: &str) -> Option<AluOp> {
+  Some(match mnemonic {
+    "add" => AluOp::Add,
+    "sub" => AluOp::Sub,
+    "sll" => AluOp::Sll,
+    "slt" => AluOp::Slt,
+    "sltu" => AluOp::Sltu,
+    "xor" => AluOp::Xor,
+    "srl" => AluOp::Srl,
+    "sra" => AluOp::Sra,
+    "or" => AluOp::Or,
+    "and" => AluOp::And,
+    "mul" => AluOp::Mul,
+    "mulh" => AluOp::Mulh,
+    "mulhsu" => AluOp::Mulhsu,
+    "mulhu" => AluOp::Mulhu,
+    "div" => AluOp::Div,
+    "divu" => AluOp::Divu,
+    "rem" => AluOp::Rem,
+    "remu" => AluOp::Remu,
+    _ => return None,
+  })
+}
+
+fn alu_imm_op(mnemonic: &str) -> Option<AluOp> {
+  Some(match mnemonic {
+    "addi" => AluOp::Add,
+    "slti" => AluOp::Slt,
+    "sltiu" => AluOp::Sltu,
+    "xori" => AluOp::Xor,
+    "ori" => AluOp::Or,
+    "andi" => AluOp::And,
+    "slli" => AluOp::Sll,
+    "srli" => AluOp::Srl,
+    "srai" => AluOp::Sra,
+    _ => return None,
+  })
+}
+
+fn load_width(mnemonic: &str) -> Option<Width> {
+  Some(match mnemonic {
+    "lb" => Width::Byte,
+    "lbu" => Width::ByteU,
+    "lh" => Width::Half,
+    "lhu" => Width::HalfU,
+    "lw" => Width::Word,
+    _ => return None,
+  })
+}
+
+fn store_width(mnemonic: &str) -> Option<Width> {
+  Some(match mnemonic {
+    "sb" => Width::Byte,
+    "sh" => Width::Half,
+    "sw" => Width::Word,
+    _ => return None,
+  })
+}
+
+/// Returns the branch condition and whether the operands are swapped (`bgt a, b` is `blt b, a`).
+fn branch_op(mnemonic: &str) -> Option<(BranchOp, bool)> {
+  Some(match mnemonic {
+    "beq" => (BranchOp::Eq, false),
+    "bne" => (BranchOp::Ne, false),
+    "blt" => (BranchOp::Lt, false),
+    "bge" => (BranchOp::Ge, false),
+    "bltu" => (BranchOp::Ltu, false),
+    "bgeu" => (BranchOp::Geu, false),
+    "bgt" => (BranchOp::Lt, true),
+    "ble" => (BranchOp::Ge, true),
+    "bgtu" => (BranchOp::Ltu, true),
+    "bleu" => (BranchOp::Geu, true),
+    _ => return None,
+  })
+}
+
+fn asm_error(line: usize, msg: String) -> CompileError {
+  CompileError::AsmError { msg, line }
+}
+
+fn single<'a>(line: usize, name: &str, operands: &'a [String]) -> Result<&'a String, CompileError> {
+  match operands {
+    [operand] => Ok(operand),
+    _ => Err(asm_error(line, format!("'{}' expects 1 operand, found {}", name, operands.len()))),
+  }
+}
+
+/// Strips a trailing `#` comment, ignoring `#` inside string and character literals.
+fn strip_comment(line: &str) -> &str {
+  let mut quote = None;
+  let mut escaped = false;
+  for (i, c) in line.char_indices() {
+    match quote {
+      Some(q) => {
+        if escaped {
+          escaped = false;
+        } else if c == '\\' {
+          escaped = true;
+        } else if c == q {
+          quote = None;
+        }
+      }
+      None => match c {
+        '"' | '\'' => quote = Some(c),
+        '#' => return &line[..i],
+        _ => {}
+      },
+    }
+  }
+  line
+}
+
+/// Splits a leading `label:` off a line.
+fn split_label(line: &str) -> Option<(&str, &str)> {
+  let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'))?;
+  if end > 0 && line[end..].starts_with(':') { Some((&line[..end], &line[end + 1..])) } else { None }
+}
+
+/// Splits operands on commas that are not inside string or character literals.
+fn split_operands(operands: &str) -> Vec<String> {
+  let mut parts = Vec::new();
+  let mut current = String::new();
+  let mut quote = None;
+  let mut escaped = false;
+  for c in operands.chars() {
+    match quote {
+      Some(q) => {
+        if escaped {
+          escaped = false;
+        } else if c == '\\' {
+          escaped = true;
+        } else if c == q {
+          quote = None;
+        }
+        current.push(c);
+      }
+      None => match c {
+        ',' => parts.push(std::mem::take(&mut current).trim().to_string()),
+        '"' | '\'' => {
+          quote = Some(c);
+          current.push(c);
+        }
+        _ => current.push(c),
+      },
+    }
+  }
+  if !current.trim().is_empty() || !parts.is_empty() {
+    parts.push(current.trim().to_string());
+  }
+  parts
+}
+
+fn parse_reg(line: usize, name: &str) -> Result<usize, CompileError> {
+  let reg = match name {
+    "zero" => Some(0),
+    "ra" => Some(1),
+    "sp" => Some(2),
+    "gp" => Some(3),
+    "tp" => Some(4),
+    "fp" => Some(8),
+    _ => {
+      let (prefix, num) = name.split_at(1.min(name.len()));
+      num.parse::<usize>().ok().and_then(|n| match prefix {
+        "x" if n < 32 => Some(n),
+        "t" if n < 3 => Some(5 + n),
+        "t" if n < 7 => Some(25 + n),
+        "s" if n < 2 => Some(8 + n),
+        "s" if n < 12 => Some(16 + n),
+        "a" if n < 8 => Some(10 + n),
+        _ => None,
+      })
+    }
+  };
+  reg.ok_or_else(|| asm_error(line, format!("Unknown register '{}'", name)))
+}
+
+/// Parses an integer immediate: decimal, `0x` hex, or a character literal like `'\n'`.
+fn parse_imm(line: usize, text: &str) -> Result<i32, CompileError> {
+  let invalid = || asm_error(line, format!("Invalid immediate '{}'", text));
+
+  if text.starts_with('\'') {
+    let bytes = parse_quoted(line, text, '\'')?;
+    return match bytes.as_slice() {
+      [byte] => Ok(*byte as i32),
+      _ => Err(invalid()),
+    };
+  }
+
+  let (negative, digits) = match text.strip_prefix('-') {
+    Some(rest) => (true, rest),
+    None => (false, text.strip_prefix('+').unwrap_or(text)),
+  };
+  let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+    i64::from_str_radix(hex, 16).map_err(|_| invalid())?
+  } else {
+    digits.parse::<i64>().map_err(|_| invalid())?
+  };
+  let value = if negative { -value } else { value };
+  // Hex constants may spell out the full 32-bit pattern, so accept anything that fits in either
+  // a signed or an unsigned word.
+  if value < i32::MIN as i64 || value > u32::MAX as i64 {
+    return Err(asm_error(line, format!("Immediate '{}' does not fit in 32 bits", text)));
+  }
+  Ok(value as i32)
+}
+
+fn parse_imm12(line: usize, text: &str) -> Result<i32, CompileError> {
+  let value = parse_imm(line, text)?;
+  if !(-2048..=2047).contains(&value) {
+    return Err(asm_error(line, format!("Immediate {} does not fit in 12 bits", value)));
+  }
+  Ok(value)
+}
+
+fn parse_upper(line: usize, text: &str) -> Result<i32, CompileError> {
+  let value = parse_imm(line, text)?;
+  if !(0..=0xfffff).contains(&value) {
+    return Err(asm_error(line, format!("Immediate {} does not fit in 20 bits", value)));
+  }
+  Ok(value)
+}
+
+/// Parses a memory operand `offset(reg)`, where the offset may be omitted.
+fn parse_mem(line: usize, text: &str) -> Result<(i32, usize), CompileError> {
+  let invalid = || asm_error(line, format!("Invalid memory operand '{}'", text));
+  let open = text.find('(').ok_or_else(invalid)?;
+  let inner = text[open + 1..].strip_suffix(')').ok_or_else(invalid)?;
+  let offset_text = text[..open].trim();
+  let offset = if offset_text.is_empty() { 0 } else { parse_imm12(line, offset_text)? };
+  Ok((offset, parse_reg(line, inner.trim())?))
+}
+
+fn parse_string(line: usize, text: &str) -> Result<Vec<u8>, CompileError> {
+  parse_quoted(line, text, '"')
+}
+
+/// Decodes a quoted literal with C-style escapes into bytes.
+fn parse_quoted(line: usize, text: &str, quote: char) -> Result<Vec<u8>, CompileError> {
+  let inner = text
+    .strip_prefix(quote)
+    .and_then(|rest| rest.strip_suffix(quote))
+    .filter(|_| text.len() >= 2)
+    .ok_or_else(|| asm_error(line, format!("Invalid literal {}", text)))?;
+
+  let mut bytes = Vec::new();
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    let c = if c == '\\' {
+      match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('0') => '\0',
+        // A raw byte, written as two hex digits.
+        Some('x') => {
+          let digits: String = chars.by_ref().take(2).collect();
+          let byte = u8::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|_| digits.len() == 2)
+            .ok_or_else(|| asm_error(line, format!("Invalid \\x escape in {}", text)))?;
+          bytes.push(byte);
+          continue;
+        }
+        Some(other) => other,
+        None => return Err(asm_error(line, format!("Unterminated escape in {}", text))),
+      }
+    } else {
+      c
+    };
+    let mut buf = [0; 4];
+    bytes.extend(c.encode_utf8(&mut buf).bytes());
+  }
+  Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Assembles and runs `text` as the body of `main`, after a `.data` section holding `data`.
+  /// Returns what it printed and its exit code.
+  fn run(data: &str, text: &str, input: &str) -> (String, i32) {
+    let asm =
+      format!("  .data\n{}\n  .text\n  .globl main\nmain:\n{}\n  li a0, 0\n  li a7, 10\n  ecall", data, text);
+    let mut emulator = Emulator::new(&asm).unwrap_or_else(|e| panic!("{}", e));
+    let mut output = Vec::new();
+    let code = emulator.run(&mut input.as_bytes(), &mut output).unwrap_or_else(|e| panic!("{}", e));
+    (String::from_utf8(output).unwrap(), code)
+  }
+
+  #[test]
+  fn registers_by_number_and_abi_name() {
+    let cases = [
+      ("zero", 0),
+      ("x0", 0),
+      ("ra", 1),
+      ("sp", 2),
+      ("fp", 8),
+      ("s0", 8),
+      ("s1", 9),
+      ("s2", 18),
+      ("s11", 27),
+      ("t0", 5),
+      ("t2", 7),
+      ("t3", 28),
+      ("t6", 31),
+      ("a0", 10),
+      ("a7", 17),
+      ("x31", 31),
+    ];
+    for (name, reg) in cases {
+      assert_eq!(parse_reg(1, name).unwrap(), reg, "{}", name);
+    }
+    for name in ["x32", "t7", "a8", "s12", "q1", "", "t", "a-1"] {
+      assert!(parse_reg(1, name).is_err(), "{}", name);
+    }
+  }
+
+  #[test]
+  fn immediates() {
+    let cases = [
+      ("42", 42),
+      ("-42", -42),
+      ("+7", 7),
+      ("0x1f", 31),
+      ("0XFF", 255),
+      ("-0x10", -16),
+      ("0xFFFFFFFF", -1),
+      ("-2147483648", i32::MIN),
+      ("'a'", 97),
+      ("'\\n'", 10),
+      ("'\\''", 39),
+    ];
+    for (text, value) in cases {
+      assert_eq!(parse_imm(1, text).unwrap(), value, "{}", text);
+    }
+    for text in ["", "abc", "0x", "4294967296", "-2147483649", "'ab'", "''", "1.5"] {
+      assert!(parse_imm(1, text).is_err(), "{}", text);
+    }
+    assert!(parse_imm12(1, "2047").is_ok() && parse_imm12(1, "2048").is_err());
+    assert!(parse_imm12(1, "-2048").is_ok() && parse_imm12(1, "-2049").is_err());
+  }
+
+  #[test]
+  fn word_directive_resolves_labels() {
+    // A jump table whose entries refer to labels defined after it, in the text segment.
+    let data = "  .align 2\ntable:\n  .word second, first, 7";
+    let text = "
+  la t0, table
+  lw t1, 0(t0)
+  jr t1
+first:
+  li a0, 1
+  li a7, 1
+  ecall
+  j done
+second:
+  li a0, 2
+  li a7, 1
+  ecall
+  lw t1, 4(t0)
+  jr t1
+done:
+  lw a0, 8(t0)
+  li a7, 1
+  ecall";
+    assert_eq!(run(data, text, "").0, "217");
+  }
+
+  #[test]
+  fn word_directive_rejects_unknown_labels() {
+    let asm = "  .data\n  .word nowhere\n  .text\nmain:\n  ecall";
+    assert!(matches!(Emulator::new(asm), Err(CompileError::AsmError { .. })));
+  }
+
+  #[test]
+  fn swapped_branch_operands() {
+    let program =
+      Assembler::default().assemble("  .text\nmain:\n  bleu t0, t1, main\n  bgtu a0, a1, main").unwrap();
+    assert!(matches!(program.text[0], Instr::Branch { op: BranchOp::Geu, rs1: 6, rs2: 5, .. }));
+    assert!(matches!(program.text[1], Instr::Branch { op: BranchOp::Ltu, rs1: 11, rs2: 10, .. }));
+
+    // As unsigned values, -1 is the largest word.
+    let text = "
+  li t0, -1
+  li t1, 1
+  bgtu t0, t1, gt
+  li a0, 0
+  j check_le
+gt:
+  li a0, 1
+check_le:
+  li a7, 1
+  ecall
+  bleu t0, t1, le
+  bleu t1, t1, le
+  li a0, 0
+  j end
+le:
+  li a0, 1
+end:
+  li a7, 1
+  ecall";
+    assert_eq!(run("", text, "").0, "11");
+  }
+
+  #[test]
+  fn division_never_traps() {
+    let min = i32::MIN as u32;
+    let minus_one = -1i32 as u32;
+    assert_eq!(alu(AluOp::Div, 7, 0), u32::MAX);
+    assert_eq!(alu(AluOp::Rem, 7, 0), 7);
+    assert_eq!(alu(AluOp::Divu, 7, 0), u32::MAX);
+    assert_eq!(alu(AluOp::Remu, 7, 0), 7);
+    assert_eq!(alu(AluOp::Div, min, minus_one), min);
+    assert_eq!(alu(AluOp::Rem, min, minus_one), 0);
+    assert_eq!(alu(AluOp::Div, -7i32 as u32, 2), -3i32 as u32);
+    assert_eq!(alu(AluOp::Rem, -7i32 as u32, 2), -1i32 as u32);
+  }
+
+  #[test]
+  fn read_string_truncates_and_discards_the_rest_of_the_line() {
+    let text = "
+  li a0, 16
+  li a7, 9
+  ecall
+  mv s1, a0
+  li a1, 4
+  li a7, 8
+  ecall
+  li a7, 4
+  ecall
+  li a0, '|'
+  li a7, 11
+  ecall
+  mv a0, s1
+  li a1, 16
+  li a7, 8
+  ecall
+  li a7, 4
+  ecall";
+    // The first read keeps 3 bytes and drops the rest of its line; the second fits its newline.
+    assert_eq!(run("", text, "abcdef\nxy\n").0, "abc|xy\n");
+  }
+
+  #[test]
+  fn exit_code_comes_from_a0() {
+    assert_eq!(run("", "  li a0, 3\n  li a7, 10\n  ecall", "").1, 3);
+  }
+}
@@ -11,12 +11,29 @@ impl Span {
   pub fn new(line: usize, col: usize, length: usize) -> Self {
     Span { line, col, length }
   }
+
+  /// Span from the start of `self` to the end of `end`. Since a span covers a single
+  /// line, a range ending on a later line is cut off at the end of `self`.
+  pub fn to(self, end: Span) -> Span {
+    if end.line == self.line && end.col + end.length >= self.col {
+      Span::new(self.line, self.col, end.col + end.length - self.col)
+    } else {
+      self
+    }
+  }
 }
 
+/// A single reported problem. The lexer and parser collect these and keep going
+/// instead of stopping at the first error.
+pub type Diagnostic = CompileError;
+
 #[derive(Debug)]
 pub enum CompileError {
   LexError { msg: String, span: Span },
   ParseError { msg: String, span: Option<Span> },
+  CodegenError { msg: String },
+  AsmError { msg: String, line: usize },
+  RuntimeError { msg: String },
 }
 
 impl CompileError {
@@ -30,6 +47,9 @@ impl CompileError {
           format!("Parser error: {}", msg)
         }
       }
+      CompileError::CodegenError { msg } => format!("Codegen error: {}", msg),
+      CompileError::AsmError { msg, line } => format!("Assembler error at line {}: {}", line, msg),
+      CompileError::RuntimeError { msg } => format!("Runtime error: {}", msg),
     }
   }
 }
@@ -73,6 +93,9 @@ impl fmt::Display for CompileError {
           write!(f, "Parser error: {}", msg)
         }
       }
+      CompileError::CodegenError { msg } => write!(f, "Codegen error: {}", msg),
+      CompileError::AsmError { msg, line } => write!(f, "Assembler error at line {}: {}", line, msg),
+      CompileError::RuntimeError { msg } => write!(f, "Runtime error: {}", msg),
     }
   }
 }
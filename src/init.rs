@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+
+use crate::{
+  error::{CompileError, Diagnostic, Span},
+  parser::{Expr, ExprKind, Stmt, StmtKind},
+};
+
+/// Variables definitely assigned at some point, or `None` where control cannot reach it (after
+/// a `return`, `exit`, `break` or `continue`). `None` is the identity of `merge`, so a path that
+/// never arrives does not weaken the paths that do.
+type Assigned = Option<HashSet<String>>;
+
+/// Where the paths joining `a` and `b` agree: only what both of them assigned.
+fn merge(a: Assigned, b: Assigned) -> Assigned {
+  match (a, b) {
+    (None, other) | (other, None) => other,
+    (Some(a), Some(b)) => Some(a.intersection(&b).cloned().collect()),
+  }
+}
+
+/// The state after a loop whose `condition` was checked on entry: either the condition failed or
+/// a `break` left the loop. Without a condition, or with a literal `true` one, only a `break` can.
+fn after_loop(condition: Option<&Expr>, entry: Assigned, breaks: Assigned) -> Assigned {
+  match condition {
+    None | Some(Expr { kind: ExprKind::Bool(true), .. }) => breaks,
+    Some(_) => merge(entry, breaks),
+  }
+}
+
+/// The states a loop is left or restarted with, collected from its `break`s and `continue`s.
+struct LoopExits {
+  label: Option<String>,
+  breaks: Assigned,
+  continues: Assigned,
+}
+
+/// Flow-sensitive check that every variable is assigned on all paths leading to each read.
+///
+/// Only variables created by a plain assignment can be read unassigned: a `let` always has an
+/// initializer, and so do parameters and range-loop variables.
+pub struct InitCheck {
+  assigned: Assigned,
+  /// Names declared with `let` in each enclosing block, innermost last.
+  blocks: Vec<HashSet<String>>,
+  loops: Vec<LoopExits>,
+  errors: Vec<Diagnostic>,
+}
+
+impl InitCheck {
+  /// Starts at the top level with `assigned` already holding a value, as earlier REPL input
+  /// leaves them.
+  pub fn new(assigned: HashSet<String>) -> Self {
+    InitCheck { assigned: Some(assigned), blocks: Vec::new(), loops: Vec::new(), errors: Vec::new() }
+  }
+
+  /// Checks top-level statements, then returns the variables assigned once they have all run
+  /// (`None` if they cannot finish) along with the errors found.
+  pub fn check(mut self, stmts: &[Stmt]) -> (Assigned, Vec<Diagnostic>) {
+    self.stmts(stmts);
+    (self.assigned, self.errors)
+  }
+
+  fn stmts(&mut self, stmts: &[Stmt]) {
+    for stmt in stmts {
+      self.stmt(stmt);
+    }
+  }
+
+  /// Checks `stmts` as a `{}` block, whose `let` variables end with it.
+  fn block(&mut self, stmts: &[Stmt]) {
+    self.blocks.push(HashSet::new());
+    self.stmts(stmts);
+    self.blocks.pop();
+  }
+
+  fn stmt(&mut self, stmt: &Stmt) {
+    match &stmt.kind {
+      StmtKind::Let { var, expr } => {
+        self.expr(expr);
+        match self.blocks.last_mut() {
+          Some(block) => {
+            block.insert(var.clone());
+          }
+          // A `let` outside any block declares in the function scope, like an assignment.
+          None => self.assign(var),
+        }
+      }
+      StmtKind::Assign { var, expr } => {
+        self.expr(expr);
+        self.assign(var);
+      }
+      StmtKind::CompoundAssign { var, expr, .. } => {
+        self.read(var, Span::new(stmt.span.line, stmt.span.col, var.len()));
+        self.expr(expr);
+      }
+      StmtKind::IndexAssign { var, index, expr } => {
+        self.read(var, Span::new(stmt.span.line, stmt.span.col, var.len()));
+        self.expr(index);
+        self.expr(expr);
+      }
+      StmtKind::Print { expr } | StmtKind::Expr(expr) => self.expr(expr),
+      StmtKind::PrintLn { expr } => {
+        if let Some(expr) = expr {
+          self.expr(expr);
+        }
+      }
+      StmtKind::While { label, condition, body } => {
+        self.expr(condition);
+        let entry = self.assigned.clone();
+        let exits = self.loop_body(label, body);
+        self.assigned = after_loop(Some(condition), entry, exits.breaks);
+      }
+      StmtKind::For { label, init, condition, update, body } => {
+        // The header is a block of its own, holding any `let` in the init.
+        self.blocks.push(HashSet::new());
+        self.stmts(init);
+        if let Some(condition) = condition {
+          self.expr(condition);
+        }
+        let entry = self.assigned.clone();
+        let exits = self.loop_body(label, body);
+        // The update runs after the body finishes or continues.
+        self.assigned = merge(self.assigned.take(), exits.continues);
+        self.stmts(update);
+        self.assigned = after_loop(condition.as_ref(), entry, exits.breaks);
+        self.blocks.pop();
+      }
+      StmtKind::ForRange { label, var, start, end, body, .. } => {
+        self.expr(start);
+        self.expr(end);
+        self.blocks.push(HashSet::from([var.clone()]));
+        let entry = self.assigned.clone();
+        let exits = self.loop_body(label, body);
+        self.assigned = merge(entry, exits.breaks);
+        self.blocks.pop();
+      }
+      StmtKind::Break(label) => {
+        let assigned = self.assigned.take();
+        let exits = self.target(label);
+        exits.breaks = merge(exits.breaks.take(), assigned);
+      }
+      StmtKind::Continue(label) => {
+        let assigned = self.assigned.take();
+        let exits = self.target(label);
+        exits.continues = merge(exits.continues.take(), assigned);
+      }
+      StmtKind::Block(body) => self.block(body),
+      StmtKind::If { branches, else_body } => {
+        let entry = self.assigned.clone();
+        // Every branch that can run, merged once the chain ends.
+        let mut taken = None;
+        for (condition, body) in branches {
+          self.expr(condition);
+          self.block(body);
+          let assigned = std::mem::replace(&mut self.assigned, entry.clone());
+          // A literal condition always takes the same branch, as it does for `while true`.
+          match condition.kind {
+            ExprKind::Bool(true) => {
+              self.assigned = merge(taken, assigned);
+              return;
+            }
+            ExprKind::Bool(false) => {}
+            _ => taken = merge(taken, assigned),
+          }
+        }
+        // No condition held: the else block runs, or nothing does.
+        if let Some(else_body) = else_body {
+          self.block(else_body);
+        }
+        self.assigned = merge(taken, self.assigned.take());
+      }
+      StmtKind::Match { value, arms, default } => {
+        self.expr(value);
+        let entry = self.assigned.clone();
+        // The parser made the arms exhaustive, so without a `_` arm one of them always runs.
+        let mut taken = None;
+        for body in arms.iter().map(|arm| &arm.body).chain(default) {
+          self.block(body);
+          taken = merge(taken, std::mem::replace(&mut self.assigned, entry.clone()));
+        }
+        self.assigned = taken;
+      }
+      StmtKind::Const { .. } => {}
+      StmtKind::Exit(code) | StmtKind::Return(code) => {
+        if let Some(code) = code {
+          self.expr(code);
+        }
+        self.assigned = None;
+      }
+      StmtKind::Function { params, body, .. } => {
+        // Functions cannot see the variables around them, only their parameters.
+        let params = params.iter().map(|(param, _)| param.clone()).collect();
+        let (_, errors) = InitCheck::new(params).check(body);
+        self.errors.extend(errors);
+      }
+    }
+  }
+
+  /// Checks a loop body entered with the current state, returning how the loop was left.
+  fn loop_body(&mut self, label: &Option<String>, body: &[Stmt]) -> LoopExits {
+    self.loops.push(LoopExits { label: label.clone(), breaks: None, continues: None });
+    // Later iterations start with at least what the first one did, so checking the body once
+    // from the loop's entry covers them all.
+    self.block(body);
+    self.loops.pop().expect("InitCheck: loop stack is empty")
+  }
+
+  fn target(&mut self, label: &Option<String>) -> &mut LoopExits {
+    let exits = match label {
+      None => self.loops.last_mut(),
+      Some(label) => self.loops.iter_mut().rev().find(|exits| exits.label.as_ref() == Some(label)),
+    };
+    // The parser already rejected a `break` or `continue` without a matching loop.
+    exits.expect("InitCheck: no loop for 'break' or 'continue'")
+  }
+
+  fn is_block_local(&self, name: &str) -> bool {
+    self.blocks.iter().any(|block| block.contains(name))
+  }
+
+  fn assign(&mut self, name: &str) {
+    if self.is_block_local(name) {
+      return;
+    }
+    if let Some(assigned) = &mut self.assigned {
+      assigned.insert(name.to_string());
+    }
+  }
+
+  fn read(&mut self, name: &str, span: Span) {
+    if self.is_block_local(name) {
+      return;
+    }
+    // Unreachable code reads nothing.
+    let Some(assigned) = &mut self.assigned else { return };
+    if !assigned.contains(name) {
+      self.errors.push(CompileError::ParseError {
+        msg: format!("Variable '{}' may be uninitialized here", name),
+        span: Some(span),
+      });
+      // Report each variable once per path instead of at every later read.
+      assigned.insert(name.to_string());
+    }
+  }
+
+  fn expr(&mut self, expr: &Expr) {
+    match &expr.kind {
+      ExprKind::Var(name) => self.read(name, expr.span),
+      ExprKind::BinOp { left, right, .. } => {
+        self.expr(left);
+        self.expr(right);
+      }
+      ExprKind::Index { array, index } => {
+        self.expr(array);
+        self.expr(index);
+      }
+      ExprKind::Conditional { condition, then_expr, else_expr } => {
+        // Expressions assign nothing, so both branches start from the same state.
+        self.expr(condition);
+        self.expr(then_expr);
+        self.expr(else_expr);
+      }
+      ExprKind::UnaryOp { expr, .. } | ExprKind::Len(expr) => self.expr(expr),
+      ExprKind::Input(arg) | ExprKind::InputStr(arg) => {
+        if let Some(arg) = arg {
+          self.expr(arg);
+        }
+      }
+      ExprKind::Call { args: exprs, .. } | ExprKind::Array(exprs) => {
+        for expr in exprs {
+          self.expr(expr);
+        }
+      }
+      ExprKind::Int(_)
+      | ExprKind::Bool(_)
+      | ExprKind::Char(_)
+      | ExprKind::String(_)
+      | ExprKind::Const { .. }
+      | ExprKind::ArrayNew(_)
+      | ExprKind::Getc => {}
+    }
+  }
+}
@@ -0,0 +1,607 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::{
+  error::CompileError,
+  parser::{BinOp, ConstValue, DEFAULT_INPUT_LEN, Expr, ExprKind, Stmt, StmtKind, Type, UnaryOp},
+  scope::Scopes,
+};
+
+/// Printed before exiting with code 1, exactly like the generated `rt_bounds_fail` routine.
+const BOUNDS_FAIL_MSG: &str = "Runtime error: array index out of bounds\n";
+
+/// Most calls that may be in progress at once. Each one also nests several native frames, so a
+/// thread running deeply recursive programs needs a large stack (the CLI gives it 512 MiB).
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+#[derive(Debug, Clone)]
+enum Value {
+  Int(i32),
+  Bool(bool),
+  Char(u8),
+  String(Rc<str>),
+  /// Arrays are references, so assigning or passing one aliases the same storage.
+  Array(Rc<RefCell<Vec<i32>>>),
+}
+
+impl Value {
+  fn as_int(&self) -> i32 {
+    match self {
+      Value::Int(n) => *n,
+      other => panic!("Interpreter: Expected an integer, found {:?}", other),
+    }
+  }
+
+  fn as_bool(&self) -> bool {
+    match self {
+      Value::Bool(b) => *b,
+      other => panic!("Interpreter: Expected a boolean, found {:?}", other),
+    }
+  }
+
+  /// The text `print` would show, as `rt_itoa` renders integers for concatenation.
+  fn as_text(&self) -> Rc<str> {
+    match self {
+      Value::Int(n) => Rc::from(n.to_string()),
+      Value::Bool(b) => Rc::from(b.to_string()),
+      Value::Char(c) => Rc::from((*c as char).to_string()),
+      Value::String(s) => s.clone(),
+      Value::Array(_) => panic!("Interpreter: Cannot convert an array to a string"),
+    }
+  }
+
+  fn as_array(&self) -> &Rc<RefCell<Vec<i32>>> {
+    match self {
+      Value::Array(array) => array,
+      other => panic!("Interpreter: Expected an array, found {:?}", other),
+    }
+  }
+}
+
+impl From<&ConstValue> for Value {
+  fn from(value: &ConstValue) -> Self {
+    match value {
+      ConstValue::Int(n) => Value::Int(*n),
+      ConstValue::Bool(b) => Value::Bool(*b),
+      ConstValue::Char(c) => Value::Char(*c),
+      ConstValue::String(s) => Value::String(Rc::from(s.as_str())),
+    }
+  }
+}
+
+/// Folds a constant initializer, which the parser has checked uses only literals, constants and
+/// operators. Evaluating it here gives exactly the value it would have at run time.
+pub fn eval_const(expr: &Expr) -> ConstValue {
+  let mut interp = Interpreter::new(std::io::empty(), std::io::sink());
+  match interp.eval(expr, &mut Scopes::new()) {
+    Ok(Value::Int(n)) => ConstValue::Int(n),
+    Ok(Value::Bool(b)) => ConstValue::Bool(b),
+    Ok(Value::Char(c)) => ConstValue::Char(c),
+    Ok(Value::String(s)) => ConstValue::String(s.to_string()),
+    Ok(Value::Array(_)) | Err(_) => panic!("Interpreter: '{:?}' is not a constant expression", expr.kind),
+  }
+}
+
+struct Function {
+  params: Vec<String>,
+  ret: Type,
+  body: Vec<Stmt>,
+}
+
+/// How control leaves a statement.
+enum Flow {
+  Next,
+  Return(Option<Value>),
+  /// `break` or `continue`, with the label of the loop it targets.
+  Break(Option<String>),
+  Continue(Option<String>),
+}
+
+impl Flow {
+  /// How a loop labelled `label` proceeds after its body ended with this flow: `None` to run the
+  /// next iteration, or the flow the loop statement itself ends with.
+  fn after_iteration(self, label: &Option<String>) -> Option<Flow> {
+    let targets_this = |target: &Option<String>| target.is_none() || target == label;
+    match self {
+      Flow::Next => None,
+      Flow::Continue(target) if targets_this(&target) => None,
+      Flow::Break(target) if targets_this(&target) => Some(Flow::Next),
+      flow => Some(flow),
+    }
+  }
+}
+
+/// Why execution stopped before the program finished.
+enum Halt {
+  Exit(i32),
+  Error(CompileError),
+}
+
+impl From<CompileError> for Halt {
+  fn from(e: CompileError) -> Self {
+    Halt::Error(e)
+  }
+}
+
+/// Executes parsed programs directly, without generating assembly. Arithmetic wraps at 32 bits
+/// and division follows RISC-V (`x / 0 == -1`, `x % 0 == x`), so a program prints the same
+/// output and exits with the same code as its compiled form.
+pub struct Interpreter<R: BufRead, W: Write> {
+  input: R,
+  output: W,
+  globals: Scopes<Value>,
+  functions: HashMap<String, Rc<Function>>,
+  /// Calls in progress.
+  depth: usize,
+}
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+  pub fn new(input: R, output: W) -> Self {
+    Interpreter { input, output, globals: Scopes::new(), functions: HashMap::new(), depth: 0 }
+  }
+
+  /// Runs a program produced by `Parser::parse` and returns its exit code.
+  pub fn run(&mut self, stmts: &[Stmt]) -> Result<i32, CompileError> {
+    Ok(self.execute(stmts)?.unwrap_or(0))
+  }
+
+  /// Runs `stmts` on top of the variables and functions left by earlier calls. Returns the exit
+  /// code if the program exited, or `None` if it ran off the end.
+  pub fn execute(&mut self, stmts: &[Stmt]) -> Result<Option<i32>, CompileError> {
+    // Like the generated assembly, every function exists before the first statement runs.
+    for stmt in stmts {
+      if let StmtKind::Function { name, params, ret, body } = &stmt.kind {
+        let function = Function {
+          params: params.iter().map(|(param, _)| param.clone()).collect(),
+          ret: ret.clone(),
+          body: body.clone(),
+        };
+        self.functions.insert(name.clone(), Rc::new(function));
+      }
+    }
+
+    let mut globals = std::mem::take(&mut self.globals);
+    let result = self.exec_block(stmts, &mut globals);
+    self.globals = globals;
+
+    let code = match result {
+      Ok(Flow::Next) => None,
+      Ok(Flow::Return(_)) => panic!("Interpreter: 'return' outside of a function"),
+      Ok(Flow::Break(_) | Flow::Continue(_)) => {
+        panic!("Interpreter: 'break' or 'continue' outside of a loop")
+      }
+      Err(Halt::Exit(code)) => Some(code),
+      Err(Halt::Error(e)) => {
+        // Keep whatever was printed before the error.
+        let _ = self.output.flush();
+        return Err(e);
+      }
+    };
+    self.output.flush().map_err(io_error)?;
+    Ok(code)
+  }
+
+  fn exec_block(&mut self, stmts: &[Stmt], vars: &mut Scopes<Value>) -> Result<Flow, Halt> {
+    for stmt in stmts {
+      let flow = self.exec_stmt(stmt, vars)?;
+      if !matches!(flow, Flow::Next) {
+        return Ok(flow);
+      }
+    }
+    Ok(Flow::Next)
+  }
+
+  /// Runs `stmts` as a `{}` block, dropping its `let` variables afterwards even if it halts, so
+  /// the REPL's globals stay at the top level.
+  fn exec_scoped(&mut self, stmts: &[Stmt], vars: &mut Scopes<Value>) -> Result<Flow, Halt> {
+    vars.push();
+    let flow = self.exec_block(stmts, vars);
+    vars.pop();
+    flow
+  }
+
+  fn exec_stmt(&mut self, stmt: &Stmt, vars: &mut Scopes<Value>) -> Result<Flow, Halt> {
+    match &stmt.kind {
+      StmtKind::Let { var, expr } => {
+        let value = self.eval(expr, vars)?;
+        vars.declare(var, value);
+      }
+      StmtKind::Assign { var, expr } => {
+        let value = self.eval(expr, vars)?;
+        vars.assign(var, value);
+      }
+      StmtKind::CompoundAssign { var, op, expr } => {
+        let current = vars.get(var).cloned().unwrap();
+        let value = apply(op, current, self.eval(expr, vars)?);
+        vars.assign(var, value);
+      }
+      StmtKind::IndexAssign { var, index, expr } => {
+        // The element (and its bounds check) is resolved before the value, as in the codegen.
+        let array = vars.get(var).unwrap().as_array().clone();
+        let index = self.eval(index, vars)?.as_int();
+        self.check_bounds(index, &array)?;
+        let value = self.eval(expr, vars)?.as_int();
+        array.borrow_mut()[index as usize] = value;
+      }
+      StmtKind::Print { expr } => {
+        let value = self.eval(expr, vars)?;
+        self.print(&value)?;
+      }
+      StmtKind::PrintLn { expr } => {
+        if let Some(expr) = expr {
+          let value = self.eval(expr, vars)?;
+          self.print(&value)?;
+        }
+        self.output.write_all(b"\n").map_err(io_error)?;
+      }
+      StmtKind::While { label, condition, body } => {
+        while self.eval(condition, vars)?.as_bool() {
+          if let Some(flow) = self.exec_scoped(body, vars)?.after_iteration(label) {
+            return Ok(flow);
+          }
+        }
+      }
+      StmtKind::For { label, init, condition, update, body } => {
+        // The header is a scope of its own, holding any `let` in the init.
+        vars.push();
+        let flow = self.exec_for(label, init, condition.as_ref(), update, body, vars);
+        vars.pop();
+        return flow;
+      }
+      StmtKind::ForRange { label, var, start, end, step, body } => {
+        // The loop variable belongs to the loop, like a `let` in a C-style header.
+        let start = self.eval(start, vars)?;
+        let end = self.eval(end, vars)?.as_int();
+        vars.push();
+        vars.declare(var, start);
+        let flow = self.exec_for_range(label, var, end, *step, body, vars);
+        vars.pop();
+        return flow;
+      }
+      StmtKind::If { branches, else_body } => {
+        for (condition, body) in branches {
+          if self.eval(condition, vars)?.as_bool() {
+            return self.exec_scoped(body, vars);
+          }
+        }
+        if let Some(else_body) = else_body {
+          return self.exec_scoped(else_body, vars);
+        }
+      }
+      StmtKind::Match { value, arms, default } => {
+        let value = self.eval(value, vars)?.as_int();
+        if let Some(arm) = arms.iter().find(|arm| arm.matches(value)) {
+          return self.exec_scoped(&arm.body, vars);
+        } else if let Some(default) = default {
+          return self.exec_scoped(default, vars);
+        }
+      }
+      // Each use already carries the value.
+      StmtKind::Const { .. } => {}
+      StmtKind::Exit(code) => {
+        let code = match code {
+          Some(expr) => self.eval(expr, vars)?.as_int(),
+          None => 0,
+        };
+        return Err(Halt::Exit(code));
+      }
+      StmtKind::Block(body) => return self.exec_scoped(body, vars),
+      StmtKind::Break(label) => return Ok(Flow::Break(label.clone())),
+      StmtKind::Continue(label) => return Ok(Flow::Continue(label.clone())),
+      StmtKind::Return(value) => {
+        let value = match value {
+          Some(expr) => Some(self.eval(expr, vars)?),
+          None => None,
+        };
+        return Ok(Flow::Return(value));
+      }
+      StmtKind::Expr(expr) => {
+        self.eval(expr, vars)?;
+      }
+      // Registered up front by `run`.
+      StmtKind::Function { .. } => {}
+    }
+    Ok(Flow::Next)
+  }
+
+  fn exec_for(
+    &mut self,
+    label: &Option<String>,
+    init: &[Stmt],
+    condition: Option<&Expr>,
+    update: &[Stmt],
+    body: &[Stmt],
+    vars: &mut Scopes<Value>,
+  ) -> Result<Flow, Halt> {
+    self.exec_block(init, vars)?;
+    while match condition {
+      Some(condition) => self.eval(condition, vars)?.as_bool(),
+      None => true,
+    } {
+      if let Some(flow) = self.exec_scoped(body, vars)?.after_iteration(label) {
+        return Ok(flow);
+      }
+      self.exec_block(update, vars)?;
+    }
+    Ok(Flow::Next)
+  }
+
+  fn exec_for_range(
+    &mut self,
+    label: &Option<String>,
+    var: &str,
+    end: i32,
+    step: i32,
+    body: &[Stmt],
+    vars: &mut Scopes<Value>,
+  ) -> Result<Flow, Halt> {
+    loop {
+      let current = vars.get(var).unwrap().as_int();
+      if (step > 0 && current >= end) || (step < 0 && current <= end) {
+        return Ok(Flow::Next);
+      }
+      if let Some(flow) = self.exec_scoped(body, vars)?.after_iteration(label) {
+        return Ok(flow);
+      }
+      // Read back, since the body may have assigned the loop variable.
+      let current = vars.get(var).unwrap().as_int();
+      vars.assign(var, Value::Int(current.wrapping_add(step)));
+    }
+  }
+
+  fn eval(&mut self, expr: &Expr, vars: &mut Scopes<Value>) -> Result<Value, Halt> {
+    let value = match &expr.kind {
+      ExprKind::Int(n) => Value::Int(*n),
+      ExprKind::Bool(b) => Value::Bool(*b),
+      ExprKind::Char(c) => Value::Char(*c),
+      ExprKind::String(s) => Value::String(Rc::from(s.as_str())),
+      ExprKind::Const { value, .. } => Value::from(value),
+      // The parser proves every read follows an assignment, but a REPL entry that stopped
+      // partway can still leave a variable it was going to assign without a value.
+      ExprKind::Var(name) => vars.get(name).cloned().ok_or_else(|| CompileError::RuntimeError {
+        msg: format!("Variable '{}' is read before it is assigned", name),
+      })?,
+      ExprKind::BinOp { op: op @ (BinOp::AND | BinOp::OR), left, right } => {
+        let left = self.eval(left, vars)?.as_bool();
+        // `a && b` is decided by a false `a`, `a || b` by a true one.
+        let result = if left != matches!(op, BinOp::AND) { left } else { self.eval(right, vars)?.as_bool() };
+        Value::Bool(result)
+      }
+      ExprKind::BinOp { op, left, right } => {
+        let left = self.eval(left, vars)?;
+        apply(op, left, self.eval(right, vars)?)
+      }
+      ExprKind::Conditional { condition, then_expr, else_expr } => {
+        let branch = if self.eval(condition, vars)?.as_bool() { then_expr } else { else_expr };
+        self.eval(branch, vars)?
+      }
+      ExprKind::UnaryOp { op, expr } => {
+        let value = self.eval(expr, vars)?;
+        match op {
+          UnaryOp::Not => Value::Bool(!value.as_bool()),
+          UnaryOp::Neg => Value::Int(value.as_int().wrapping_neg()),
+          UnaryOp::BitNot => Value::Int(!value.as_int()),
+        }
+      }
+      ExprKind::Input(prompt) => {
+        if let Some(prompt) = prompt {
+          let prompt = self.eval(prompt, vars)?;
+          self.print(&prompt)?;
+        }
+        Value::Int(self.read_int()?)
+      }
+      ExprKind::InputStr(max_len) => {
+        let max_len = match max_len {
+          Some(max_len) => self.eval(max_len, vars)?.as_int(),
+          None => DEFAULT_INPUT_LEN,
+        };
+        Value::String(self.read_str(max_len)?)
+      }
+      ExprKind::Getc => Value::Char(self.read_char()?),
+      ExprKind::Call { name, args, .. } => {
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+          values.push(self.eval(arg, vars)?);
+        }
+        self.call(name, values)?
+      }
+      ExprKind::Array(elems) => {
+        let mut values = Vec::with_capacity(elems.len());
+        for elem in elems {
+          values.push(self.eval(elem, vars)?.as_int());
+        }
+        Value::Array(Rc::new(RefCell::new(values)))
+      }
+      ExprKind::ArrayNew(size) => Value::Array(Rc::new(RefCell::new(vec![0; *size as usize]))),
+      ExprKind::Index { array, index } => {
+        let array = self.eval(array, vars)?;
+        let index = self.eval(index, vars)?.as_int();
+        let array = array.as_array();
+        self.check_bounds(index, array)?;
+        Value::Int(array.borrow()[index as usize])
+      }
+      ExprKind::Len(array) => Value::Int(self.eval(array, vars)?.as_array().borrow().len() as i32),
+    };
+    Ok(value)
+  }
+
+  fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Halt> {
+    let function =
+      self.functions.get(name).cloned().unwrap_or_else(|| panic!("Interpreter: Unknown function '{}'", name));
+    let mut locals: Scopes<Value> = function.params.iter().cloned().zip(args).collect();
+
+    // Recursion runs on the native stack, so stop it before the stack runs out.
+    if self.depth == MAX_CALL_DEPTH {
+      return Err(Halt::Error(CompileError::RuntimeError {
+        msg: format!("Call to '{}' exceeds the limit of {} nested calls", name, MAX_CALL_DEPTH),
+      }));
+    }
+    self.depth += 1;
+    let flow = self.exec_block(&function.body, &mut locals);
+    // Decremented on errors too, since the REPL keeps the interpreter after one.
+    self.depth -= 1;
+
+    let value = match flow? {
+      Flow::Return(Some(value)) => value,
+      // Falling off the end or a bare `return;` yields the default, like `gen_default_value`.
+      Flow::Break(_) | Flow::Continue(_) => panic!("Interpreter: 'break' or 'continue' outside of a loop"),
+      Flow::Return(None) | Flow::Next => match function.ret {
+        Type::String => Value::String(Rc::from("")),
+        Type::Bool => Value::Bool(false),
+        Type::Char => Value::Char(0),
+        _ => Value::Int(0),
+      },
+    };
+    Ok(value)
+  }
+
+  fn print(&mut self, value: &Value) -> Result<(), CompileError> {
+    match value {
+      Value::Int(n) => write!(self.output, "{}", n),
+      Value::Bool(b) => write!(self.output, "{}", b),
+      Value::Char(c) => self.output.write_all(&[*c]),
+      Value::String(s) => self.output.write_all(s.as_bytes()),
+      Value::Array(_) => panic!("Interpreter: Cannot print an array"),
+    }
+    .map_err(io_error)
+  }
+
+  fn read_int(&mut self) -> Result<i32, CompileError> {
+    self.output.flush().map_err(io_error)?;
+    let mut line = String::new();
+    if self.input.read_line(&mut line).map_err(io_error)? == 0 {
+      return Err(CompileError::RuntimeError {
+        msg: "Reached end of input while reading an integer".to_string(),
+      });
+    }
+    line
+      .trim()
+      .parse::<i32>()
+      .map_err(|_| CompileError::RuntimeError { msg: format!("Invalid integer input: '{}'", line.trim()) })
+  }
+
+  /// Reads a line like `rt_readstr`: the newline is dropped and at most `max_len` bytes are kept,
+  /// with the rest of the line discarded.
+  fn read_str(&mut self, max_len: i32) -> Result<Rc<str>, CompileError> {
+    self.output.flush().map_err(io_error)?;
+    let mut line = Vec::new();
+    if self.input.read_until(b'\n', &mut line).map_err(io_error)? == 0 {
+      return Err(CompileError::RuntimeError {
+        msg: "Reached end of input while reading a string".to_string(),
+      });
+    }
+    if line.last() == Some(&b'\n') {
+      line.pop();
+    }
+    line.truncate(max_len.max(0) as usize);
+    Ok(Rc::from(String::from_utf8_lossy(&line)))
+  }
+
+  /// Reads a single byte, leaving the rest of the line for later reads.
+  fn read_char(&mut self) -> Result<u8, CompileError> {
+    self.output.flush().map_err(io_error)?;
+    let Some(&byte) = self.input.fill_buf().map_err(io_error)?.first() else {
+      return Err(CompileError::RuntimeError {
+        msg: "Reached end of input while reading a character".to_string(),
+      });
+    };
+    self.input.consume(1);
+    Ok(byte)
+  }
+
+  /// Fails the way `rt_bounds_fail` does: print the message and exit with code 1. Indices are
+  /// compared unsigned like the generated `bltu`, so negative ones are out of range too.
+  fn check_bounds(&mut self, index: i32, array: &RefCell<Vec<i32>>) -> Result<(), Halt> {
+    if (index as u32) < array.borrow().len() as u32 {
+      return Ok(());
+    }
+    self.output.write_all(BOUNDS_FAIL_MSG.as_bytes()).map_err(io_error)?;
+    Err(Halt::Exit(1))
+  }
+}
+
+/// `left op right` for any operator but `&&` and `||`, which decide whether `right` runs.
+fn apply(op: &BinOp, left: Value, right: Value) -> Value {
+  match (left, right) {
+    (Value::Int(left), Value::Int(right)) => match compare(op, left.cmp(&right)) {
+      Some(result) => Value::Bool(result),
+      None => Value::Int(binop(op, left, right)),
+    },
+    // Byte-wise like `rt_strcmp`, which is what `str`'s ordering does.
+    (Value::String(left), Value::String(right)) if !matches!(op, BinOp::Add) => {
+      Value::Bool(compare(op, left.cmp(&right)).expect("Interpreter: strings only support comparisons"))
+    }
+    // Char arithmetic wraps at a byte, like the `andi` the generator emits after it.
+    (Value::Char(left), Value::Int(right)) => Value::Char(byte_offset(op, left, right)),
+    (Value::Int(left), Value::Char(right)) => Value::Char(byte_offset(op, right, left)),
+    (Value::Char(left), Value::Char(right)) => match compare(op, left.cmp(&right)) {
+      Some(result) => Value::Bool(result),
+      None => Value::Int(left as i32 - right as i32),
+    },
+    (Value::Bool(left), Value::Bool(right)) => {
+      Value::Bool(compare(op, left.cmp(&right)).expect("Interpreter: booleans only support equality"))
+    }
+    (left, right) => Value::String(Rc::from(format!("{}{}", left.as_text(), right.as_text()))),
+  }
+}
+
+/// The result of comparison `op` given how the operands order, or `None` if `op` is not one.
+fn compare(op: &BinOp, ordering: Ordering) -> Option<bool> {
+  Some(match op {
+    BinOp::LT => ordering.is_lt(),
+    BinOp::LTE => ordering.is_le(),
+    BinOp::GT => ordering.is_gt(),
+    BinOp::GTE => ordering.is_ge(),
+    BinOp::Eq => ordering.is_eq(),
+    BinOp::Neq => ordering.is_ne(),
+    _ => return None,
+  })
+}
+
+/// `c + n` or `c - n`, wrapped to a byte.
+fn byte_offset(op: &BinOp, c: u8, n: i32) -> u8 {
+  match op {
+    BinOp::Add => c.wrapping_add(n as u8),
+    BinOp::Sub => c.wrapping_sub(n as u8),
+    _ => unreachable!("Interpreter: chars only support + and - with integers"),
+  }
+}
+
+fn binop(op: &BinOp, left: i32, right: i32) -> i32 {
+  match op {
+    BinOp::Add => left.wrapping_add(right),
+    BinOp::Sub => left.wrapping_sub(right),
+    BinOp::Mul => left.wrapping_mul(right),
+    BinOp::Div => {
+      if right == 0 {
+        -1
+      } else {
+        left.wrapping_div(right)
+      }
+    }
+    BinOp::Mod => {
+      if right == 0 {
+        left
+      } else {
+        left.wrapping_rem(right)
+      }
+    }
+    BinOp::BitAnd => left & right,
+    BinOp::BitOr => left | right,
+    BinOp::BitXor => left ^ right,
+    // Only the low 5 bits of the shift amount count, as with `sll`/`sra`.
+    BinOp::LShift => left.wrapping_shl(right as u32),
+    BinOp::RShift => left.wrapping_shr(right as u32),
+    BinOp::LT | BinOp::LTE | BinOp::GT | BinOp::GTE | BinOp::Eq | BinOp::Neq => {
+      unreachable!("Interpreter: comparisons are evaluated by `compare`")
+    }
+    BinOp::AND | BinOp::OR => unreachable!("Interpreter: short-circuit operators are evaluated separately"),
+  }
+}
+
+fn io_error(e: std::io::Error) -> CompileError {
+  CompileError::RuntimeError { msg: format!("I/O error: {}", e) }
+}
@@ -1,4 +1,4 @@
-use crate::error::{CompileError, Span};
+use crate::error::{CompileError, Diagnostic, Span};
 
 #[derive(Debug)]
 pub struct Token {
@@ -11,11 +11,24 @@ pub enum TokenKind {
   Int(i32),
   Ident(String),
   String(String),
+  Char(u8),
   Plus,
   Minus,
   Star,
   Slash,
   Assign,
+  PlusAssign,
+  MinusAssign,
+  StarAssign,
+  SlashAssign,
+  PercentAssign,
+  AndAssign,
+  OrAssign,
+  CaretAssign,
+  LShiftAssign,
+  RShiftAssign,
+  PlusPlus,
+  MinusMinus,
   EqEq,
   BangEq,
   AndAnd,
@@ -28,10 +41,20 @@ pub enum TokenKind {
   Tilde,
   Percent,
   Semicolon,
+  Comma,
+  Colon,
+  Question,
+  Arrow,
+  FatArrow,
+  DotDot,
+  DotDotEq,
+  Underscore,
   LParen,
   RParen,
   LBrace,
   RBrace,
+  LBracket,
+  RBracket,
   LT,
   GT,
   LTE,
@@ -41,10 +64,20 @@ pub enum TokenKind {
   PrintLn,
   Input,
   While,
+  For,
+  Break,
+  Continue,
   If,
   Else,
   Comment,
   Exit,
+  Fn,
+  Let,
+  Const,
+  Match,
+  Return,
+  True,
+  False,
   Eof,
 }
 
@@ -53,24 +86,41 @@ pub struct Lexer {
   pos: usize,
   line: usize,
   col: usize,
+  error_limit: usize,
 }
 
 impl Lexer {
   pub fn new(input: &str) -> Self {
-    Lexer { input: input.chars().collect(), pos: 0, line: 1, col: 1 }
+    Lexer { input: input.chars().collect(), pos: 0, line: 1, col: 1, error_limit: usize::MAX }
   }
 
-  pub fn tokenize(&mut self) -> Result<Vec<Token>, CompileError> {
+  /// Stops tokenizing once `limit` errors have been reported. A limit of 0 means no limit.
+  pub fn with_error_limit(mut self, limit: usize) -> Self {
+    self.error_limit = if limit == 0 { usize::MAX } else { limit };
+    self
+  }
+
+  pub fn tokenize(&mut self) -> Result<Vec<Token>, Vec<Diagnostic>> {
     let mut tokens = Vec::new();
+    let mut diagnostics = Vec::new();
     loop {
-      let token = self.next_token()?;
-      if token.kind == TokenKind::Eof {
-        tokens.push(token);
-        break;
+      match self.next_token() {
+        Ok(token) => {
+          if token.kind == TokenKind::Eof {
+            tokens.push(token);
+            break;
+          }
+          tokens.push(token);
+        }
+        Err(e) => {
+          diagnostics.push(e);
+          if diagnostics.len() >= self.error_limit {
+            break;
+          }
+        }
       }
-      tokens.push(token);
     }
-    Ok(tokens)
+    if diagnostics.is_empty() { Ok(tokens) } else { Err(diagnostics) }
   }
 
   fn peek(&self) -> Option<char> {
@@ -195,6 +245,82 @@ impl Lexer {
     })
   }
 
+  /// Reads a character literal after its opening `'`. Only ASCII characters are allowed, since
+  /// a `char` is a single byte.
+  fn read_char(&mut self) -> Result<(u8, usize), CompileError> {
+    let start_line = self.line;
+    let start_col = self.col - 1;
+    let (ch, length) = match self.peek() {
+      Some('\\') => {
+        self.next();
+        let escaped = match self.peek() {
+          Some('n') => '\n',
+          Some('t') => '\t',
+          Some('r') => '\r',
+          Some('0') => '\0',
+          Some(other) => other,
+          None => {
+            return Err(CompileError::LexError {
+              msg: "Unterminated escape in character literal".to_string(),
+              span: Span::new(start_line, start_col, 2),
+            });
+          }
+        };
+        self.next();
+        (escaped, 4)
+      }
+      Some('\'') => {
+        self.next();
+        return Err(CompileError::LexError {
+          msg: "Empty character literal".to_string(),
+          span: Span::new(start_line, start_col, 2),
+        });
+      }
+      Some('\n') | None => {
+        return Err(CompileError::LexError {
+          msg: "Unterminated character literal".to_string(),
+          span: Span::new(start_line, start_col, 1),
+        });
+      }
+      Some(ch) => {
+        self.next();
+        (ch, 3)
+      }
+    };
+
+    if self.peek() != Some('\'') {
+      // Skip the rest of a literal like 'ab' so its closing quote does not start another one.
+      let mut extra = 0;
+      while let Some(ch) = self.peek() {
+        if ch == '\'' || ch == '\n' {
+          break;
+        }
+        extra += 1;
+        self.next();
+      }
+      if self.peek() == Some('\'') {
+        self.next();
+        return Err(CompileError::LexError {
+          msg: "Character literal must hold a single character".to_string(),
+          span: Span::new(start_line, start_col, length + extra),
+        });
+      }
+      return Err(CompileError::LexError {
+        msg: "Unterminated character literal".to_string(),
+        span: Span::new(start_line, start_col, length - 1 + extra),
+      });
+    }
+    self.next();
+
+    if !ch.is_ascii() {
+      return Err(CompileError::LexError {
+        msg: format!("Character literal '{}' is not ASCII", ch),
+        span: Span::new(start_line, start_col, length),
+      });
+    }
+    Ok((ch as u8, length))
+  }
+
   fn next_token(&mut self) -> Result<Token, CompileError> {
     self.skip_whitespace();
 
@@ -207,29 +333,66 @@ impl Lexer {
         let (kind, length) = match ch {
           '+' => {
             self.next();
-            (TokenKind::Plus, 1)
+            if let Some('+') = self.peek() {
+              self.next();
+              (TokenKind::PlusPlus, 2)
+            } else if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::PlusAssign, 2)
+            } else {
+              (TokenKind::Plus, 1)
+            }
           }
           '-' => {
             self.next();
-            (TokenKind::Minus, 1)
+            if let Some('>') = self.peek() {
+              self.next();
+              (TokenKind::Arrow, 2)
+            } else if let Some('-') = self.peek() {
+              self.next();
+              (TokenKind::MinusMinus, 2)
+            } else if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::MinusAssign, 2)
+            } else {
+              (TokenKind::Minus, 1)
+            }
           }
           '*' => {
             self.next();
-            (TokenKind::Star, 1)
+            if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::StarAssign, 2)
+            } else {
+              (TokenKind::Star, 1)
+            }
           }
           '/' => {
             self.next();
-            (TokenKind::Slash, 1)
+            if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::SlashAssign, 2)
+            } else {
+              (TokenKind::Slash, 1)
+            }
           }
           '%' => {
             self.next();
-            (TokenKind::Percent, 1)
+            if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::PercentAssign, 2)
+            } else {
+              (TokenKind::Percent, 1)
+            }
           }
           '=' => {
             self.next();
             if let Some('=') = self.peek() {
               self.next();
               (TokenKind::EqEq, 2)
+            } else if let Some('>') = self.peek() {
+              self.next();
+              (TokenKind::FatArrow, 2)
             } else {
               (TokenKind::Assign, 1)
             }
@@ -239,6 +402,9 @@ impl Lexer {
             if let Some('&') = self.peek() {
               self.next();
               (TokenKind::AndAnd, 2)
+            } else if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::AndAssign, 2)
             } else {
               (TokenKind::And, 1)
             }
@@ -248,13 +414,21 @@ impl Lexer {
             if let Some('|') = self.peek() {
               self.next();
               (TokenKind::OrOr, 2)
+            } else if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::OrAssign, 2)
             } else {
               (TokenKind::Or, 1)
             }
           }
           '^' => {
             self.next();
-            (TokenKind::Caret, 1)
+            if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::CaretAssign, 2)
+            } else {
+              (TokenKind::Caret, 1)
+            }
           }
           '~' => {
             self.next();
@@ -273,6 +447,33 @@ impl Lexer {
             self.next();
             (TokenKind::Semicolon, 1)
           }
+          ',' => {
+            self.next();
+            (TokenKind::Comma, 1)
+          }
+          ':' => {
+            self.next();
+            (TokenKind::Colon, 1)
+          }
+          '?' => {
+            self.next();
+            (TokenKind::Question, 1)
+          }
+          '.' if self.input.get(self.pos + 1) == Some(&'.') => {
+            self.next();
+            self.next();
+            if let Some('=') = self.peek() {
+              self.next();
+              (TokenKind::DotDotEq, 3)
+            } else {
+              (TokenKind::DotDot, 2)
+            }
+          }
+          // A lone `_`, the wildcard pattern. Identifiers cannot start with one.
+          '_' if !self.input.get(self.pos + 1).is_some_and(|&c| c.is_alphanumeric() || c == '_') => {
+            self.next();
+            (TokenKind::Underscore, 1)
+          }
           '(' => {
             self.next();
             (TokenKind::LParen, 1)
@@ -289,11 +490,24 @@ impl Lexer {
             self.next();
             (TokenKind::RBrace, 1)
           }
+          '[' => {
+            self.next();
+            (TokenKind::LBracket, 1)
+          }
+          ']' => {
+            self.next();
+            (TokenKind::RBracket, 1)
+          }
           '<' => {
             self.next();
             if let Some('<') = self.peek() {
               self.next();
-              (TokenKind::LShift, 2)
+              if let Some('=') = self.peek() {
+                self.next();
+                (TokenKind::LShiftAssign, 3)
+              } else {
+                (TokenKind::LShift, 2)
+              }
             } else if let Some('=') = self.peek() {
               self.next();
               (TokenKind::LTE, 2)
@@ -305,7 +519,12 @@ impl Lexer {
             self.next();
             if let Some('>') = self.peek() {
               self.next();
-              (TokenKind::RShift, 2)
+              if let Some('=') = self.peek() {
+                self.next();
+                (TokenKind::RShiftAssign, 3)
+              } else {
+                (TokenKind::RShift, 2)
+              }
             } else if let Some('=') = self.peek() {
               self.next();
               (TokenKind::GTE, 2)
@@ -318,6 +537,11 @@ impl Lexer {
             let (s, len) = self.read_string()?;
             (TokenKind::String(s), len)
           }
+          '\'' => {
+            self.next();
+            let (c, len) = self.read_char()?;
+            (TokenKind::Char(c), len)
+          }
           _ if ch.is_numeric() => {
             let (n, len) = self.read_number()?;
             (TokenKind::Int(n), len)
@@ -330,13 +554,25 @@ impl Lexer {
               "println" => TokenKind::PrintLn,
               "input" => TokenKind::Input,
               "while" => TokenKind::While,
+              "for" => TokenKind::For,
+              "break" => TokenKind::Break,
+              "continue" => TokenKind::Continue,
               "if" => TokenKind::If,
               "else" => TokenKind::Else,
+              "fn" => TokenKind::Fn,
+              "let" => TokenKind::Let,
+              "const" => TokenKind::Const,
+              "match" => TokenKind::Match,
+              "return" => TokenKind::Return,
+              "true" => TokenKind::True,
+              "false" => TokenKind::False,
               _ => TokenKind::Ident(id),
             };
             (kind, len)
           }
           _ => {
+            // Consume the character so tokenizing can resume after it.
+            self.next();
             return Err(CompileError::LexError {
               msg: format!("Unexpected character: '{}'", ch),
               span: Span::new(start_line, start_col, 1),
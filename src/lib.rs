@@ -1,4 +1,9 @@
 pub mod codegen;
+pub mod emulator;
 pub mod error;
+pub mod init;
+pub mod interp;
 pub mod lexer;
 pub mod parser;
+pub mod runtime;
+pub mod scope;
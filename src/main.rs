@@ -1,19 +1,37 @@
+mod repl;
+
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::thread;
+
+use clap::{Parser as ClapParser, Subcommand};
 
-use clap::Parser as ClapParser;
+use pine_rv::{
+  codegen::CodeGen,
+  emulator::Emulator,
+  error::Diagnostic,
+  interp::Interpreter,
+  lexer::Lexer,
+  parser::{Parser, Stmt},
+};
 
-use pine_rv::{codegen::CodeGen, lexer::Lexer, parser::Parser};
+/// Native stack for the interpreter: enough for `MAX_CALL_DEPTH` calls even in a debug build.
+const INTERPRETER_STACK_SIZE: usize = 512 << 20;
 
 #[derive(ClapParser, Debug)]
 #[command(name = "pine-rv")]
 #[command(about = "A compiler for the Pine language targeting RISC-V assembly", long_about = None)]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+  #[command(subcommand)]
+  command: Option<Command>,
+
   /// Input file (.pine)
-  #[arg(value_name = "FILE")]
-  input: PathBuf,
+  #[arg(value_name = "FILE", required = true)]
+  input: Option<PathBuf>,
 
   /// Output file (.s). If not specified, uses input filename with .s extension
   #[arg(short, long, value_name = "FILE")]
@@ -34,35 +52,159 @@ struct Args {
   /// Dump AST and variable types to file
   #[arg(long, value_name = "FILE")]
   dump_ast: Option<PathBuf>,
+
+  #[command(flatten)]
+  options: CompileOptions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Compile a program and run it on the built-in RV32IM emulator
+  Run {
+    /// Input file (.pine)
+    #[arg(value_name = "FILE")]
+    input: PathBuf,
+
+    #[command(flatten)]
+    options: CompileOptions,
+  },
+  /// Run a program with the tree-walking interpreter, without generating assembly
+  Interpret {
+    /// Input file (.pine)
+    #[arg(value_name = "FILE")]
+    input: PathBuf,
+
+    /// Stop after reporting this many errors (0 for no limit)
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    error_limit: usize,
+  },
+  /// Start an interactive session that runs statements as they are entered
+  Repl,
+}
+
+#[derive(clap::Args, Debug)]
+struct CompileOptions {
+  /// Skip the runtime bounds check on array accesses
+  #[arg(long)]
+  no_bounds_check: bool,
+
+  /// Stop after reporting this many errors (0 for no limit)
+  #[arg(long, value_name = "N", default_value_t = 20)]
+  error_limit: usize,
+
+  /// Number of temporary registers available to expressions (3-7), to exercise stack spilling
+  #[arg(long, hide = true, value_name = "N", value_parser = clap::value_parser!(u8).range(3..=7))]
+  regs: Option<u8>,
+}
+
+impl CompileOptions {
+  fn codegen(&self) -> CodeGen {
+    let codegen = CodeGen::new().with_bounds_check(!self.no_bounds_check);
+    match self.regs {
+      Some(regs) => codegen.with_regs(regs as usize),
+      None => codegen,
+    }
+  }
 }
 
 fn main() {
   let args = Args::parse();
 
-  if args.input.extension().and_then(|s| s.to_str()) != Some("pine") {
+  match args.command {
+    Some(Command::Run { input, options }) => run(&input, &options),
+    Some(Command::Interpret { input, error_limit }) => {
+      with_interpreter_stack(move || interpret(&input, error_limit))
+    }
+    Some(Command::Repl) => with_interpreter_stack(|| process::exit(repl::run())),
+    None => compile(args),
+  }
+}
+
+/// Runs `f` on a thread whose stack fits `MAX_CALL_DEPTH` nested interpreter calls.
+fn with_interpreter_stack(f: impl FnOnce() + Send + 'static) {
+  let thread = thread::Builder::new().stack_size(INTERPRETER_STACK_SIZE).spawn(f);
+  if thread.expect("Failed to start the interpreter thread").join().is_err() {
+    process::exit(101);
+  }
+}
+
+/// Compiles the program and executes it on the emulator, exiting with the program's exit code.
+fn run(input: &Path, options: &CompileOptions) -> ! {
+  let src = read_source(input);
+  let ast = parse_source(&src, options.error_limit);
+  let asm = match options.codegen().generate(&ast) {
+    Ok(asm) => asm,
+    Err(e) => {
+      eprintln!("{}", e.display_with_source(&src));
+      process::exit(1);
+    }
+  };
+
+  let result = Emulator::new(&asm)
+    .and_then(|mut emulator| emulator.run(&mut io::stdin().lock(), &mut BufWriter::new(io::stdout().lock())));
+  match result {
+    Ok(code) => process::exit(code),
+    Err(e) => {
+      eprintln!("{}", e);
+      process::exit(1);
+    }
+  }
+}
+
+/// Runs the program on the interpreter, exiting with the program's exit code.
+fn interpret(input: &Path, error_limit: usize) -> ! {
+  let src = read_source(input);
+  let ast = parse_source(&src, error_limit);
+
+  let mut interpreter = Interpreter::new(io::stdin().lock(), BufWriter::new(io::stdout().lock()));
+  match interpreter.run(&ast) {
+    Ok(code) => process::exit(code),
+    Err(e) => {
+      eprintln!("{}", e);
+      process::exit(1);
+    }
+  }
+}
+
+/// Lexes and parses `src`, reporting every diagnostic and exiting if there are any.
+fn parse_source(src: &str, error_limit: usize) -> Vec<Stmt> {
+  let tokens = match Lexer::new(src).with_error_limit(error_limit).tokenize() {
+    Ok(tokens) => tokens,
+    Err(diagnostics) => report_and_exit(&diagnostics, src),
+  };
+  match Parser::new(tokens).with_error_limit(error_limit).parse() {
+    Ok((ast, _)) => ast,
+    Err(diagnostics) => report_and_exit(&diagnostics, src),
+  }
+}
+
+fn read_source(input: &Path) -> String {
+  if input.extension().and_then(|s| s.to_str()) != Some("pine") {
     eprintln!("Error: Input file must have the extension .pine");
     process::exit(1);
   }
 
-  let src = match fs::read_to_string(&args.input) {
+  match fs::read_to_string(input) {
     Ok(content) => content,
     Err(e) => {
-      eprintln!("Error reading file '{}': {}", args.input.display(), e);
+      eprintln!("Error reading file '{}': {}", input.display(), e);
       process::exit(1);
     }
-  };
+  }
+}
+
+fn compile(args: Args) {
+  let input = args.input.as_deref().expect("clap requires FILE when no subcommand is given");
+  let src = read_source(input);
 
   if args.verbose {
-    println!("Compiling {}...", args.input.display());
+    println!("Compiling {}...", input.display());
   }
 
-  let mut lexer = Lexer::new(&src);
+  let mut lexer = Lexer::new(&src).with_error_limit(args.options.error_limit);
   let tokens = match lexer.tokenize() {
     Ok(tokens) => tokens,
-    Err(e) => {
-      eprintln!("{}", e.display_with_source(&src));
-      process::exit(1);
-    }
+    Err(diagnostics) => report_and_exit(&diagnostics, &src),
   };
 
   if args.verbose {
@@ -84,13 +226,10 @@ fn main() {
     }
   }
 
-  let mut parser = Parser::new(tokens);
+  let mut parser = Parser::new(tokens).with_error_limit(args.options.error_limit);
   let (ast, var_types) = match parser.parse() {
     Ok(ast) => ast,
-    Err(e) => {
-      eprintln!("{}", e.display_with_source(&src));
-      process::exit(1);
-    }
+    Err(diagnostics) => report_and_exit(&diagnostics, &src),
   };
 
   if args.verbose {
@@ -112,8 +251,13 @@ fn main() {
     }
   }
 
-  let mut codegen = CodeGen::new();
-  let asm = codegen.generate(&ast);
+  let asm = match args.options.codegen().generate(&ast) {
+    Ok(asm) => asm,
+    Err(e) => {
+      eprintln!("{}", e.display_with_source(&src));
+      process::exit(1);
+    }
+  };
 
   if args.verbose {
     println!("Code generation complete");
@@ -122,7 +266,7 @@ fn main() {
   if args.print {
     println!("{}", asm);
   } else {
-    let output_path = args.output.unwrap_or_else(|| args.input.with_extension("s"));
+    let output_path = args.output.unwrap_or_else(|| input.with_extension("s"));
 
     match fs::write(&output_path, asm) {
       Ok(_) => {
@@ -139,3 +283,12 @@ fn main() {
     }
   }
 }
+
+fn report_and_exit(diagnostics: &[Diagnostic], src: &str) -> ! {
+  for diagnostic in diagnostics {
+    eprintln!("{}\n", diagnostic.display_with_source(src));
+  }
+  let plural = if diagnostics.len() == 1 { "" } else { "s" };
+  eprintln!("Compilation failed with {} error{}", diagnostics.len(), plural);
+  process::exit(1);
+}
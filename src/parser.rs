@@ -1,55 +1,274 @@
 use crate::{
-  error::{CompileError, Span},
+  error::{CompileError, Diagnostic, Span},
+  init::InitCheck,
+  interp,
   lexer::{Token, TokenKind},
+  scope::Scopes,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
   Int,
   String,
+  /// `true` or `false`, stored as 1 or 0.
+  Bool,
+  /// A single ASCII character, stored as its byte value.
+  Char,
+  /// Reference to a fixed-size block of integers, laid out as `[len, elem0, elem1, ...]`.
+  Array,
 }
 
-#[derive(Debug)]
-pub enum Expr {
+#[derive(Debug, Clone)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
   Int(i32),
+  Bool(bool),
+  Char(u8),
   Var(String),
+  /// A reference to a `const`, carrying its value.
+  Const {
+    name: String,
+    value: ConstValue,
+  },
   String(String),
-  BinOp { op: BinOp, left: Box<Expr>, right: Box<Expr> },
-  UnaryOp { op: UnaryOp, expr: Box<Expr> },
-  Input,
+  BinOp {
+    op: BinOp,
+    left: Box<Expr>,
+    right: Box<Expr>,
+  },
+  UnaryOp {
+    op: UnaryOp,
+    expr: Box<Expr>,
+  },
+  /// `condition ? then_expr : else_expr`, also written `if condition then a else b`.
+  Conditional {
+    condition: Box<Expr>,
+    then_expr: Box<Expr>,
+    else_expr: Box<Expr>,
+  },
+  Call {
+    name: String,
+    args: Vec<Expr>,
+    ret: Type,
+  },
+  Array(Vec<Expr>),
+  ArrayNew(i32),
+  Index {
+    array: Box<Expr>,
+    index: Box<Expr>,
+  },
+  Len(Box<Expr>),
+  Input(Option<Box<Expr>>),
+  InputStr(Option<Box<Expr>>),
+  Getc,
 }
 
 impl Expr {
-  pub fn get_type(&self, var_types: &HashMap<String, Type>) -> Result<Type, CompileError> {
-    match self {
-      Expr::Int(_) => Ok(Type::Int),
-      Expr::String(_) => Ok(Type::String),
-      Expr::Input => Ok(Type::Int),
-      Expr::Var(name) => var_types
-        .get(name)
-        .cloned()
-        .ok_or_else(|| CompileError::ParseError { msg: format!("Unknown variable: {}", name), span: None }),
-      Expr::BinOp { op, left, right } => {
+  pub fn new(kind: ExprKind, span: Span) -> Self {
+    Expr { kind, span }
+  }
+
+  /// The value `var op= rhs` stores: `var op rhs`, reading `var` (found at `var_span`) once.
+  pub fn compound(var: &str, var_span: Span, op: BinOp, rhs: Expr) -> Self {
+    let span = var_span.to(rhs.span);
+    let var = Expr::new(ExprKind::Var(var.to_string()), var_span);
+    Expr::new(ExprKind::BinOp { op, left: Box::new(var), right: Box::new(rhs) }, span)
+  }
+
+  pub fn get_type(&self, var_types: &Scopes<Type>) -> Result<Type, CompileError> {
+    match &self.kind {
+      ExprKind::Int(_) => Ok(Type::Int),
+      ExprKind::String(_) => Ok(Type::String),
+      ExprKind::Bool(_) => Ok(Type::Bool),
+      ExprKind::Char(_) | ExprKind::Getc => Ok(Type::Char),
+      ExprKind::Const { value, .. } => Ok(value.get_type()),
+      ExprKind::Input(prompt) => {
+        if let Some(prompt) = prompt
+          && prompt.get_type(var_types)? != Type::String
+        {
+          return Err(CompileError::ParseError {
+            msg: "input() prompt must be a string".to_string(),
+            span: Some(prompt.span),
+          });
+        }
+        Ok(Type::Int)
+      }
+      ExprKind::InputStr(max_len) => {
+        if let Some(max_len) = max_len
+          && max_len.get_type(var_types)? != Type::Int
+        {
+          return Err(CompileError::ParseError {
+            msg: "input_str() maximum length must be an integer".to_string(),
+            span: Some(max_len.span),
+          });
+        }
+        Ok(Type::String)
+      }
+      ExprKind::Call { ret, .. } => Ok(ret.clone()),
+      ExprKind::ArrayNew(_) => Ok(Type::Array),
+      ExprKind::Array(elems) => {
+        for elem in elems {
+          if elem.get_type(var_types)? != Type::Int {
+            return Err(CompileError::ParseError {
+              msg: "Array elements must be integers".to_string(),
+              span: Some(elem.span),
+            });
+          }
+        }
+        Ok(Type::Array)
+      }
+      ExprKind::Index { array, index } => {
+        if array.get_type(var_types)? != Type::Array {
+          return Err(CompileError::ParseError {
+            msg: "Only arrays can be indexed".to_string(),
+            span: Some(array.span),
+          });
+        }
+        if index.get_type(var_types)? != Type::Int {
+          return Err(CompileError::ParseError {
+            msg: "Array index must be an integer".to_string(),
+            span: Some(index.span),
+          });
+        }
+        Ok(Type::Int)
+      }
+      ExprKind::Len(array) => {
+        if array.get_type(var_types)? != Type::Array {
+          return Err(CompileError::ParseError {
+            msg: "len() requires an array".to_string(),
+            span: Some(array.span),
+          });
+        }
+        Ok(Type::Int)
+      }
+      ExprKind::Var(name) => var_types.get(name).cloned().ok_or_else(|| CompileError::ParseError {
+        msg: format!("Unknown variable: {}", name),
+        span: Some(self.span),
+      }),
+      ExprKind::BinOp { op, left, right } => {
         let left_type = left.get_type(var_types)?;
         let right_type = right.get_type(var_types)?;
 
+        // `+` concatenates when either side is a string; an integer or boolean on the other side
+        // is converted to its text.
+        if matches!(op, BinOp::Add) && (left_type == Type::String || right_type == Type::String) {
+          let (other_type, other) =
+            if left_type == Type::String { (right_type, right) } else { (left_type, left) };
+          if other_type == Type::Array {
+            return Err(CompileError::ParseError {
+              msg: "Cannot concatenate an array with a string".to_string(),
+              span: Some(other.span),
+            });
+          }
+          return Ok(Type::String);
+        }
+
+        // A char moves by an integer offset, and two chars are an integer distance apart.
+        if matches!(op, BinOp::Add | BinOp::Sub) && (left_type == Type::Char || right_type == Type::Char) {
+          return match (op, &left_type, &right_type) {
+            (_, Type::Char, Type::Int) | (BinOp::Add, Type::Int, Type::Char) => Ok(Type::Char),
+            (BinOp::Sub, Type::Char, Type::Char) => Ok(Type::Int),
+            _ => Err(CompileError::ParseError {
+              msg: format!(
+                "Binary operation {:?} is not defined for {:?} and {:?}",
+                op, left_type, right_type
+              ),
+              span: Some(self.span),
+            }),
+          };
+        }
+
+        match op {
+          BinOp::AND | BinOp::OR => {
+            if left_type != Type::Bool || right_type != Type::Bool {
+              let operand = if left_type != Type::Bool { left } else { right };
+              return Err(CompileError::ParseError {
+                msg: format!("Logical operation {:?} requires Bool operands", op),
+                span: Some(operand.span),
+              });
+            }
+            return Ok(Type::Bool);
+          }
+          // Equality works on any matching pair except arrays; ordering on integers, chars and
+          // strings, which compare byte by byte.
+          BinOp::Eq | BinOp::Neq | BinOp::LT | BinOp::LTE | BinOp::GT | BinOp::GTE => {
+            let is_equality = matches!(op, BinOp::Eq | BinOp::Neq);
+            if left_type == Type::Array || right_type == Type::Array {
+              let operand = if left_type == Type::Array { left } else { right };
+              return Err(CompileError::ParseError {
+                msg: "Arrays cannot be compared".to_string(),
+                span: Some(operand.span),
+              });
+            }
+            if left_type != right_type {
+              return Err(CompileError::ParseError {
+                msg: format!("Cannot compare {:?} with {:?}", left_type, right_type),
+                span: Some(right.span),
+              });
+            }
+            if matches!(left_type, Type::Int | Type::Char | Type::String)
+              || (is_equality && left_type == Type::Bool)
+            {
+              return Ok(Type::Bool);
+            }
+          }
+          _ => {}
+        }
+
         if left_type != Type::Int || right_type != Type::Int {
+          let operand = if left_type != Type::Int { left } else { right };
           return Err(CompileError::ParseError {
             msg: format!("Binary operation {:?} requires integer operands", op),
-            span: None,
+            span: Some(operand.span),
           });
         }
 
         Ok(Type::Int)
       }
-      Expr::UnaryOp { op, expr } => {
+      ExprKind::Conditional { condition, then_expr, else_expr } => {
+        let condition_type = condition.get_type(var_types)?;
+        if condition_type != Type::Bool {
+          return Err(CompileError::ParseError {
+            msg: format!("Condition must be a Bool, found {:?}", condition_type),
+            span: Some(condition.span),
+          });
+        }
+        let then_type = then_expr.get_type(var_types)?;
+        let else_type = else_expr.get_type(var_types)?;
+        if then_type != else_type {
+          return Err(CompileError::ParseError {
+            msg: format!(
+              "Both branches of a conditional must have the same type, found {:?} and {:?}",
+              then_type, else_type
+            ),
+            span: Some(else_expr.span),
+          });
+        }
+        Ok(then_type)
+      }
+      ExprKind::UnaryOp { op: UnaryOp::Not, expr } => {
+        if expr.get_type(var_types)? != Type::Bool {
+          return Err(CompileError::ParseError {
+            msg: "Unary operation Not requires a Bool operand".to_string(),
+            span: Some(expr.span),
+          });
+        }
+
+        Ok(Type::Bool)
+      }
+      ExprKind::UnaryOp { op, expr } => {
         let expr_type = expr.get_type(var_types)?;
 
         if expr_type != Type::Int {
           return Err(CompileError::ParseError {
             msg: format!("Unary operation {:?} requires an integer operand", op),
-            span: None,
+            span: Some(expr.span),
           });
         }
 
@@ -59,14 +278,14 @@ impl Expr {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum UnaryOp {
   Not,
   Neg,
   BitNot,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BinOp {
   Add,
   Sub,
@@ -91,6 +310,7 @@ pub enum BinOp {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum Prec {
   Lowest,
+  Conditional,
   Or,
   And,
   BitOr,
@@ -104,33 +324,260 @@ enum Prec {
   Unary,
 }
 
-#[derive(Debug)]
-pub enum Stmt {
+#[derive(Debug, Clone)]
+pub struct Stmt {
+  pub kind: StmtKind,
+  pub span: Span,
+}
+
+impl Stmt {
+  pub fn new(kind: StmtKind, span: Span) -> Self {
+    Stmt { kind, span }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+  Let { var: String, expr: Expr },
+  Const { name: String, value: ConstValue },
   Assign { var: String, expr: Expr },
+  CompoundAssign { var: String, op: BinOp, expr: Expr },
+  IndexAssign { var: String, index: Expr, expr: Expr },
   Print { expr: Expr },
   PrintLn { expr: Option<Expr> },
-  While { condition: Expr, body: Vec<Stmt> },
-  If { condition: Expr, then_body: Vec<Stmt>, else_body: Option<Vec<Stmt>> },
+  While { label: Option<String>, condition: Expr, body: Vec<Stmt> },
+  For { label: Option<String>, init: Vec<Stmt>, condition: Option<Expr>, update: Vec<Stmt>, body: Vec<Stmt> },
+  ForRange { label: Option<String>, var: String, start: Expr, end: Expr, step: i32, body: Vec<Stmt> },
+  Break(Option<String>),
+  Continue(Option<String>),
+  Block(Vec<Stmt>),
+  If { branches: Vec<(Expr, Vec<Stmt>)>, else_body: Option<Vec<Stmt>> },
+  Match { value: Expr, arms: Vec<MatchArm>, default: Option<Vec<Stmt>> },
   Exit(Option<Expr>),
+  Function { name: String, params: Vec<(String, Type)>, ret: Type, body: Vec<Stmt> },
+  Return(Option<Expr>),
+  Expr(Expr),
+}
+
+/// The value of a `const`, folded while parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+  Int(i32),
+  Bool(bool),
+  Char(u8),
+  String(String),
+}
+
+impl ConstValue {
+  pub fn get_type(&self) -> Type {
+    match self {
+      ConstValue::Int(_) => Type::Int,
+      ConstValue::Bool(_) => Type::Bool,
+      ConstValue::Char(_) => Type::Char,
+      ConstValue::String(_) => Type::String,
+    }
+  }
+}
+
+/// One arm of a `match`: the values it handles as inclusive ranges, where a single value `n` is
+/// `(n, n)`. Arms never overlap, so at most one matches.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+  pub ranges: Vec<(i32, i32)>,
+  pub body: Vec<Stmt>,
+}
+
+impl MatchArm {
+  pub fn matches(&self, value: i32) -> bool {
+    self.ranges.iter().any(|&(lo, hi)| lo <= value && value <= hi)
+  }
+}
+
+/// Parsed statements together with the types of the top-level variables.
+pub type Program = (Vec<Stmt>, HashMap<String, Type>);
+
+/// Maximum number of parameters, one per argument register `a0-a7`.
+pub const MAX_PARAMS: usize = 8;
+
+/// Characters `input_str()` keeps when no maximum length is given.
+pub const DEFAULT_INPUT_LEN: i32 = 256;
+
+/// Most elements `array(n)` may create (4 MiB of storage).
+pub const MAX_ARRAY_LEN: i32 = 1 << 20;
+
+/// Functions built into the language. Their names are only special when followed by `(`, so
+/// they remain usable as variable names.
+const BUILTINS: [&str; 4] = ["input_str", "getc", "array", "len"];
+
+#[derive(Debug, Clone)]
+struct FnSig {
+  params: Vec<Type>,
+  /// `None` until the first `return expr;` (or a `-> type` annotation) fixes it.
+  ret: Option<Type>,
+  /// Set when a call came before the return type was known and took it to be `Int`, so the
+  /// `return` that disagrees can say why.
+  ret_assumed: bool,
+}
+
+/// A parsed `fn name(params) -> type` header.
+struct Signature {
+  name: String,
+  name_span: Span,
+  params: Vec<(String, Type)>,
+  ret: Option<Type>,
 }
 
 pub struct Parser {
   tokens: Vec<Token>,
   pos: usize,
-  var_types: HashMap<String, Type>,
+  var_types: Scopes<Type>,
+  /// Constants, which are declared at the top level and visible everywhere after.
+  consts: HashMap<String, ConstValue>,
+  /// Top-level variables assigned on every path through the input parsed so far.
+  assigned: HashSet<String>,
+  functions: HashMap<String, FnSig>,
+  /// Functions whose signature was collected ahead of time but whose definition has not been
+  /// parsed yet.
+  predeclared: HashSet<String>,
+  current_fn: Option<String>,
+  /// Labels of the loops enclosing the statement being parsed, innermost last.
+  loops: Vec<Option<String>>,
+  /// Span of the most recently consumed token, where the node being parsed ends.
+  prev_span: Span,
+  diagnostics: Vec<Diagnostic>,
+  error_limit: usize,
 }
 
 impl Parser {
   pub fn new(tokens: Vec<Token>) -> Self {
-    Parser { tokens, pos: 0, var_types: HashMap::new() }
+    Parser {
+      tokens,
+      pos: 0,
+      var_types: Scopes::new(),
+      consts: HashMap::new(),
+      assigned: HashSet::new(),
+      functions: HashMap::new(),
+      predeclared: HashSet::new(),
+      current_fn: None,
+      loops: Vec::new(),
+      prev_span: Span::new(0, 0, 0),
+      diagnostics: Vec::new(),
+      error_limit: usize::MAX,
+    }
+  }
+
+  /// Stops parsing once `limit` errors have been reported. A limit of 0 means no limit.
+  pub fn with_error_limit(mut self, limit: usize) -> Self {
+    self.error_limit = if limit == 0 { usize::MAX } else { limit };
+    self
   }
 
-  pub fn parse(&mut self) -> Result<(Vec<Stmt>, HashMap<String, Type>), CompileError> {
+  pub fn parse(&mut self) -> Result<Program, Vec<Diagnostic>> {
+    self.declare_functions();
     let mut stmts = Vec::new();
-    while self.peek().kind != TokenKind::Eof {
-      stmts.push(self.parse_statement()?);
+    while self.peek().kind != TokenKind::Eof && !self.at_error_limit() {
+      let start = self.pos;
+      let stmt = match self.peek().kind {
+        TokenKind::Fn => self.parse_function(),
+        TokenKind::Const => self.parse_const(),
+        _ => self.parse_statement(),
+      };
+      match stmt {
+        Ok(stmt) => stmts.push(stmt),
+        Err(e) => {
+          self.report(e);
+          // A stray '}' at the top level is left alone by `synchronize`.
+          if self.pos == start {
+            self.next();
+          }
+        }
+      }
+    }
+
+    // A tree with errors is missing statements, which would only lead to spurious reports.
+    if self.diagnostics.is_empty() {
+      let (assigned, errors) = InitCheck::new(self.assigned.clone()).check(&stmts);
+      self.diagnostics.extend(errors.into_iter().take(self.error_limit));
+      // Input that cannot run to its end (it always exits) leaves nothing new assigned.
+      if let (true, Some(assigned)) = (self.diagnostics.is_empty(), assigned) {
+        self.assigned = assigned;
+      }
+    }
+
+    if self.diagnostics.is_empty() {
+      Ok((stmts, self.var_types.outermost().clone()))
+    } else {
+      Err(std::mem::take(&mut self.diagnostics))
+    }
+  }
+
+  /// Parses another chunk of input in the scope left by earlier calls, so its variables and
+  /// functions stay visible. If the chunk has errors, nothing it declared is kept.
+  pub fn parse_more(&mut self, tokens: Vec<Token>) -> Result<Program, Vec<Diagnostic>> {
+    let var_types = self.var_types.clone();
+    let consts = self.consts.clone();
+    let functions = self.functions.clone();
+    self.tokens = tokens;
+    self.pos = 0;
+    let result = self.parse();
+    if result.is_err() {
+      self.var_types = var_types;
+      self.consts = consts;
+      self.functions = functions;
+    }
+    result
+  }
+
+  /// Type-checks `tokens` as a single expression in the current scope.
+  pub fn expr_type(&mut self, tokens: Vec<Token>) -> Result<Type, Diagnostic> {
+    self.tokens = tokens;
+    self.pos = 0;
+    let expr = self.parse_expr()?;
+    if self.peek().kind != TokenKind::Eof {
+      return Err(CompileError::ParseError {
+        msg: "Expected end of expression".to_string(),
+        span: Some(self.peek().span),
+      });
+    }
+    expr.get_type(&self.var_types)
+  }
+
+  fn at_error_limit(&self) -> bool {
+    self.diagnostics.len() >= self.error_limit
+  }
+
+  /// Records `error` and skips ahead to a point where parsing can resume.
+  fn report(&mut self, error: CompileError) {
+    self.diagnostics.push(error);
+    self.synchronize();
+  }
+
+  /// Panic-mode recovery: discards tokens up to and including the next `;`, or up to
+  /// the `}` closing the current block. Blocks opened along the way are skipped whole.
+  fn synchronize(&mut self) {
+    let mut depth = 0;
+    loop {
+      match self.peek().kind {
+        TokenKind::Eof => return,
+        TokenKind::Semicolon if depth == 0 => {
+          self.next();
+          return;
+        }
+        TokenKind::LBrace => depth += 1,
+        TokenKind::RBrace => {
+          if depth == 0 {
+            return;
+          }
+          depth -= 1;
+          if depth == 0 {
+            self.next();
+            return;
+          }
+        }
+        _ => {}
+      }
+      self.next();
     }
-    Ok((stmts, self.var_types.clone()))
   }
 
   fn peek(&self) -> &Token {
@@ -140,27 +587,383 @@ impl Parser {
 
   fn next(&mut self) {
     if self.pos < self.tokens.len() {
+      self.prev_span = self.tokens[self.pos].span;
       self.pos += 1;
     }
   }
 
+  /// Span from `start` up to the end of the last consumed token.
+  fn span_from(&self, start: Span) -> Span {
+    start.to(self.prev_span)
+  }
+
+  fn expect(&mut self, kind: TokenKind, msg: &str) -> Result<(), CompileError> {
+    if self.peek().kind != kind {
+      return Err(CompileError::ParseError { msg: msg.to_string(), span: Some(self.peek().span) });
+    }
+    self.next();
+    Ok(())
+  }
+
+  /// Like `expect`, for a name that is only a keyword where the grammar asks for it.
+  fn expect_word(&mut self, word: &str, msg: &str) -> Result<(), CompileError> {
+    if !matches!(&self.peek().kind, TokenKind::Ident(name) if name == word) {
+      return Err(CompileError::ParseError { msg: msg.to_string(), span: Some(self.peek().span) });
+    }
+    self.next();
+    Ok(())
+  }
+
+  /// Parses a `{}` block, whose `let` declarations end with it.
+  fn parse_block(&mut self, after: &str, body: &str) -> Result<Vec<Stmt>, CompileError> {
+    self.expect(TokenKind::LBrace, &format!("Expected '{{' after {}", after))?;
+    self.var_types.push();
+    let stmts = self.parse_block_stmts(body);
+    self.var_types.pop();
+    stmts
+  }
+
+  fn parse_block_stmts(&mut self, body: &str) -> Result<Vec<Stmt>, CompileError> {
+    let mut stmts = Vec::new();
+    while self.peek().kind != TokenKind::RBrace {
+      if self.at_error_limit() {
+        return Ok(stmts);
+      }
+      if self.peek().kind == TokenKind::Eof {
+        return Err(CompileError::ParseError {
+          msg: format!("Expected '}}' after {}", body),
+          span: Some(self.peek().span),
+        });
+      }
+      match self.parse_statement() {
+        Ok(stmt) => stmts.push(stmt),
+        Err(e) => self.report(e),
+      }
+    }
+    self.next();
+    Ok(stmts)
+  }
+
+  fn parse_type(&mut self) -> Result<Type, CompileError> {
+    let ty = match &self.peek().kind {
+      TokenKind::Ident(name) if name == "int" => Type::Int,
+      TokenKind::Ident(name) if name == "string" => Type::String,
+      TokenKind::Ident(name) if name == "bool" => Type::Bool,
+      TokenKind::Ident(name) if name == "char" => Type::Char,
+      TokenKind::Ident(name) if name == "array" => Type::Array,
+      _ => {
+        return Err(CompileError::ParseError {
+          msg: format!("Expected a type, found {:?}", self.peek().kind),
+          span: Some(self.peek().span),
+        });
+      }
+    };
+    self.next();
+    Ok(ty)
+  }
+
+  /// Collects the signature of every function in the input, so calls may come before the
+  /// definition. Errors are left for `parse_function` to report.
+  fn declare_functions(&mut self) {
+    self.predeclared.clear();
+    let (start, prev_span) = (self.pos, self.prev_span);
+    let mut depth = 0;
+    while self.peek().kind != TokenKind::Eof {
+      match self.peek().kind {
+        TokenKind::LBrace => depth += 1,
+        TokenKind::RBrace => depth -= 1,
+        TokenKind::Fn if depth == 0 => {
+          if let Ok(Signature { name, params, ret, .. }) = self.parse_signature()
+            && !self.functions.contains_key(&name)
+          {
+            let params = params.into_iter().map(|(_, ty)| ty).collect();
+            self.functions.insert(name.clone(), FnSig { params, ret, ret_assumed: false });
+            self.predeclared.insert(name);
+          }
+          continue;
+        }
+        _ => {}
+      }
+      self.next();
+    }
+    (self.pos, self.prev_span) = (start, prev_span);
+  }
+
+  /// Parses `fn name(params) -> type`, up to the body.
+  fn parse_signature(&mut self) -> Result<Signature, CompileError> {
+    self.next();
+    let name_span = self.peek().span;
+    let name = match &self.peek().kind {
+      TokenKind::Ident(name) => name.clone(),
+      _ => {
+        return Err(CompileError::ParseError {
+          msg: "Expected function name after 'fn'".to_string(),
+          span: Some(name_span),
+        });
+      }
+    };
+    if BUILTINS.contains(&name.as_str()) {
+      return Err(CompileError::ParseError {
+        msg: format!("'{}' is a builtin function and cannot be redefined", name),
+        span: Some(name_span),
+      });
+    }
+    self.next();
+
+    self.expect(TokenKind::LParen, "Expected '(' after function name")?;
+    let mut params: Vec<(String, Type)> = Vec::new();
+    while self.peek().kind != TokenKind::RParen {
+      let param_span = self.peek().span;
+      let param = match &self.peek().kind {
+        TokenKind::Ident(param) => param.clone(),
+        _ => {
+          return Err(CompileError::ParseError {
+            msg: "Expected parameter name".to_string(),
+            span: Some(param_span),
+          });
+        }
+      };
+      self.next();
+
+      self.check_not_const(&param, param_span, "redeclare")?;
+      if params.iter().any(|(p, _)| *p == param) {
+        return Err(CompileError::ParseError {
+          msg: format!("Duplicate parameter '{}'", param),
+          span: Some(param_span),
+        });
+      }
+
+      let ty = if self.peek().kind == TokenKind::Colon {
+        self.next();
+        self.parse_type()?
+      } else {
+        Type::Int
+      };
+      params.push((param, ty));
+
+      if self.peek().kind == TokenKind::Comma {
+        self.next();
+      } else if self.peek().kind != TokenKind::RParen {
+        return Err(CompileError::ParseError {
+          msg: "Expected ',' or ')' in parameter list".to_string(),
+          span: Some(self.peek().span),
+        });
+      }
+    }
+    self.next();
+
+    if params.len() > MAX_PARAMS {
+      return Err(CompileError::ParseError {
+        msg: format!(
+          "Function '{}' has {} parameters, at most {} are supported",
+          name,
+          params.len(),
+          MAX_PARAMS
+        ),
+        span: Some(name_span),
+      });
+    }
+
+    let ret = if self.peek().kind == TokenKind::Arrow {
+      self.next();
+      let ret_span = self.peek().span;
+      let ret = self.parse_type()?;
+      if ret == Type::Array {
+        return Err(CompileError::ParseError {
+          msg: "Functions cannot return arrays".to_string(),
+          span: Some(ret_span),
+        });
+      }
+      Some(ret)
+    } else {
+      None
+    };
+
+    Ok(Signature { name, name_span, params, ret })
+  }
+
+  fn parse_function(&mut self) -> Result<Stmt, CompileError> {
+    let start = self.peek().span;
+    let Signature { name, name_span, params, ret } = self.parse_signature()?;
+
+    // A predeclared signature may already hold a return type assumed by an earlier call.
+    if !self.predeclared.remove(&name) {
+      if self.functions.contains_key(&name) {
+        return Err(CompileError::ParseError {
+          msg: format!("Function '{}' is already defined", name),
+          span: Some(name_span),
+        });
+      }
+      let params = params.iter().map(|(_, ty)| ty.clone()).collect();
+      self.functions.insert(name.clone(), FnSig { params, ret, ret_assumed: false });
+    }
+
+    let outer_vars = std::mem::replace(&mut self.var_types, params.iter().cloned().collect());
+    self.current_fn = Some(name.clone());
+    let body = self.parse_block("function signature", "function body");
+    self.current_fn = None;
+    self.var_types = outer_vars;
+    let body = body?;
+
+    let ret = self.functions[&name].ret.clone().unwrap_or(Type::Int);
+    self.functions.get_mut(&name).unwrap().ret = Some(ret.clone());
+
+    Ok(Stmt::new(StmtKind::Function { name, params, ret, body }, start.to(name_span)))
+  }
+
+  fn parse_call(&mut self, name: String, span: Span) -> Result<Expr, CompileError> {
+    if BUILTINS.contains(&name.as_str()) {
+      return self.parse_builtin(&name, span);
+    }
+    let sig = match self.functions.get(&name) {
+      Some(sig) => sig.clone(),
+      None => {
+        return Err(CompileError::ParseError {
+          msg: format!("Function '{}' not found", name),
+          span: Some(span),
+        });
+      }
+    };
+
+    self.expect(TokenKind::LParen, "Expected '(' after function name")?;
+    let mut args = Vec::new();
+    while self.peek().kind != TokenKind::RParen {
+      let arg = self.parse_expr()?;
+      let arg_type = arg.get_type(&self.var_types)?;
+
+      if let Some(param_type) = sig.params.get(args.len())
+        && *param_type != arg_type
+      {
+        return Err(CompileError::ParseError {
+          msg: format!(
+            "Argument {} of '{}' must be {:?}, found {:?}",
+            args.len() + 1,
+            name,
+            param_type,
+            arg_type
+          ),
+          span: Some(arg.span),
+        });
+      }
+      args.push(arg);
+
+      if self.peek().kind == TokenKind::Comma {
+        self.next();
+      } else if self.peek().kind != TokenKind::RParen {
+        return Err(CompileError::ParseError {
+          msg: "Expected ',' or ')' in argument list".to_string(),
+          span: Some(self.peek().span),
+        });
+      }
+    }
+    self.next();
+
+    if args.len() != sig.params.len() {
+      return Err(CompileError::ParseError {
+        msg: format!(
+          "Function '{}' takes {} arguments, but {} were given",
+          name,
+          sig.params.len(),
+          args.len()
+        ),
+        span: Some(span),
+      });
+    }
+
+    // A call inside the function's own body, or before its definition, can come before the
+    // first `return`. Such a function returns `Int` unless a `return` later says otherwise.
+    let ret = match sig.ret {
+      Some(ret) => ret,
+      None => {
+        let sig = self.functions.get_mut(&name).unwrap();
+        sig.ret = Some(Type::Int);
+        sig.ret_assumed = true;
+        Type::Int
+      }
+    };
+
+    Ok(Expr::new(ExprKind::Call { name, args, ret }, self.span_from(span)))
+  }
+
+  /// Parses the arguments of a builtin function, whose name `start` points at.
+  fn parse_builtin(&mut self, name: &str, start: Span) -> Result<Expr, CompileError> {
+    self.expect(TokenKind::LParen, "Expected '(' after function name")?;
+    let kind = match name {
+      "input_str" => {
+        let max_len =
+          if self.peek().kind != TokenKind::RParen { Some(Box::new(self.parse_expr()?)) } else { None };
+        self.expect(TokenKind::RParen, "Expected ')' after 'input_str('")?;
+        ExprKind::InputStr(max_len)
+      }
+      "getc" => {
+        self.expect(TokenKind::RParen, "Expected ')' after 'getc('")?;
+        ExprKind::Getc
+      }
+      "array" => {
+        let size = match self.peek().kind {
+          TokenKind::Int(n) if n > MAX_ARRAY_LEN => {
+            return Err(CompileError::ParseError {
+              msg: format!("Array size {} is larger than the limit of {} elements", n, MAX_ARRAY_LEN),
+              span: Some(self.peek().span),
+            });
+          }
+          TokenKind::Int(n) => n,
+          _ => {
+            return Err(CompileError::ParseError {
+              msg: "Array size must be an integer literal".to_string(),
+              span: Some(self.peek().span),
+            });
+          }
+        };
+        self.next();
+        self.expect(TokenKind::RParen, "Expected ')' after array size")?;
+        ExprKind::ArrayNew(size)
+      }
+      "len" => {
+        let array = self.parse_expr()?;
+        self.expect(TokenKind::RParen, "Expected ')' after 'len('")?;
+        ExprKind::Len(Box::new(array))
+      }
+      _ => unreachable!("Parser: '{}' is not a builtin", name),
+    };
+    Ok(Expr::new(kind, self.span_from(start)))
+  }
+
   fn parse_statement(&mut self) -> Result<Stmt, CompileError> {
+    let start = self.peek().span;
+    let kind = self.parse_statement_kind()?;
+    Ok(Stmt::new(kind, self.span_from(start)))
+  }
+
+  fn parse_statement_kind(&mut self) -> Result<StmtKind, CompileError> {
     match &self.peek().kind {
-      TokenKind::Ident(name) => {
-        let var = name.clone();
+      // `name: while` is a label, whereas `name: type = ...` is an annotated assignment.
+      TokenKind::Ident(label)
+        if self.tokens.get(self.pos + 1).is_some_and(|t| t.kind == TokenKind::Colon)
+          && self
+            .tokens
+            .get(self.pos + 2)
+            .is_some_and(|t| matches!(t.kind, TokenKind::While | TokenKind::For)) =>
+      {
+        let label = label.clone();
+        let span = self.peek().span;
         self.next();
-        if self.peek().kind == TokenKind::Assign {
+        self.next();
+        if self.loops.iter().flatten().any(|l| *l == label) {
+          return Err(CompileError::ParseError {
+            msg: format!("Label '{}' is already used by an enclosing loop", label),
+            span: Some(span),
+          });
+        }
+        self.parse_loop(Some(label))
+      }
+
+      TokenKind::Ident(_) | TokenKind::Let => {
+        let kind = self.parse_simple_statement()?;
+        if self.peek().kind == TokenKind::Semicolon {
           self.next();
-          let expr = self.parse_expr()?;
-          let expr_type = expr.get_type(&self.var_types)?;
-          self.var_types.insert(var.clone(), expr_type);
-          if self.peek().kind == TokenKind::Semicolon {
-            self.next();
-          }
-          Ok(Stmt::Assign { var, expr })
-        } else {
-          Err(CompileError::ParseError { msg: "Expected '='".to_string(), span: Some(self.peek().span) })
         }
+        Ok(kind)
       }
 
       TokenKind::Exit => {
@@ -172,7 +975,7 @@ impl Parser {
           if expr_type != Type::Int {
             return Err(CompileError::ParseError {
               msg: "Exit code must be an integer".to_string(),
-              span: Some(self.peek().span),
+              span: Some(expr.span),
             });
           }
 
@@ -185,7 +988,7 @@ impl Parser {
           self.next();
         }
 
-        Ok(Stmt::Exit(exit_code))
+        Ok(StmtKind::Exit(exit_code))
       }
 
       TokenKind::Print | TokenKind::PrintLn => {
@@ -195,105 +998,149 @@ impl Parser {
           if self.peek().kind == TokenKind::Semicolon {
             self.next();
           }
-          return Ok(Stmt::PrintLn { expr: None });
+          return Ok(StmtKind::PrintLn { expr: None });
         }
 
         let expr = self.parse_expr()?;
+        if expr.get_type(&self.var_types)? == Type::Array {
+          return Err(CompileError::ParseError {
+            msg: "Cannot print an array".to_string(),
+            span: Some(expr.span),
+          });
+        }
 
         if self.peek().kind == TokenKind::Semicolon {
           self.next();
         }
 
-        if is_newline { Ok(Stmt::PrintLn { expr: Some(expr) }) } else { Ok(Stmt::Print { expr }) }
+        if is_newline { Ok(StmtKind::PrintLn { expr: Some(expr) }) } else { Ok(StmtKind::Print { expr }) }
       }
 
-      TokenKind::While => {
-        self.next();
-        let condition = self.parse_expr()?;
+      TokenKind::While | TokenKind::For => self.parse_loop(None),
+
+      TokenKind::LBrace => Ok(StmtKind::Block(self.parse_block("", "block")?)),
 
-        if self.peek().kind != TokenKind::LBrace {
+      TokenKind::Break | TokenKind::Continue => {
+        let is_break = self.peek().kind == TokenKind::Break;
+        let keyword = if is_break { "break" } else { "continue" };
+        let span = self.peek().span;
+        self.next();
+        if self.loops.is_empty() {
           return Err(CompileError::ParseError {
-            msg: "Expected '{' after while condition".to_string(),
-            span: Some(self.peek().span),
+            msg: format!("'{}' outside of a loop", keyword),
+            span: Some(span),
           });
         }
 
-        self.next();
-        let mut body = Vec::new();
-        while self.peek().kind != TokenKind::RBrace {
-          if self.peek().kind == TokenKind::Eof {
-            return Err(CompileError::ParseError {
-              msg: "Expected '}' after while-loop body".to_string(),
-              span: Some(self.peek().span),
-            });
+        let label = match &self.peek().kind {
+          TokenKind::Ident(label) => {
+            let label = label.clone();
+            if !self.loops.iter().flatten().any(|l| *l == label) {
+              return Err(CompileError::ParseError {
+                msg: format!("No enclosing loop is labelled '{}'", label),
+                span: Some(self.peek().span),
+              });
+            }
+            self.next();
+            Some(label)
           }
-          let stmt = self.parse_statement()?;
-          body.push(stmt);
-        }
+          _ => None,
+        };
 
-        self.next();
+        if self.peek().kind == TokenKind::Semicolon {
+          self.next();
+        }
 
-        Ok(Stmt::While { condition, body })
+        if is_break { Ok(StmtKind::Break(label)) } else { Ok(StmtKind::Continue(label)) }
       }
 
       TokenKind::If => {
         self.next();
-        let condition = self.parse_expr()?;
+        let condition = self.parse_condition()?;
+        let then_body = self.parse_block("if condition", "if body")?;
+        let mut branches = vec![(condition, then_body)];
 
-        if self.peek().kind != TokenKind::LBrace {
-          return Err(CompileError::ParseError {
-            msg: "Expected '{' after if condition".to_string(),
-            span: Some(self.peek().span),
-          });
-        }
-
-        self.next();
-        let mut then_body = Vec::new();
-        while self.peek().kind != TokenKind::RBrace {
-          if self.peek().kind == TokenKind::Eof {
-            return Err(CompileError::ParseError {
-              msg: "Expected '}' after if body".to_string(),
-              span: Some(self.peek().span),
-            });
+        // `else if` continues the same chain instead of nesting a new one in the else block.
+        let mut else_body = None;
+        while self.peek().kind == TokenKind::Else {
+          self.next();
+          if self.peek().kind == TokenKind::If {
+            self.next();
+            let condition = self.parse_condition()?;
+            branches.push((condition, self.parse_block("else if condition", "else if body")?));
+          } else {
+            else_body = Some(self.parse_block("else", "else body")?);
+            break;
           }
-          let stmt = self.parse_statement()?;
-          then_body.push(stmt);
         }
 
-        self.next();
+        Ok(StmtKind::If { branches, else_body })
+      }
 
-        let else_body = if self.peek().kind == TokenKind::Else {
-          self.next();
+      TokenKind::Match => self.parse_match(),
+
+      TokenKind::Return => {
+        let span = self.peek().span;
+        self.next();
+        let Some(fn_name) = self.current_fn.clone() else {
+          return Err(CompileError::ParseError {
+            msg: "'return' outside of a function".to_string(),
+            span: Some(span),
+          });
+        };
 
-          if self.peek().kind != TokenKind::LBrace {
+        let value = if !matches!(self.peek().kind, TokenKind::Semicolon | TokenKind::RBrace | TokenKind::Eof)
+        {
+          let expr = self.parse_expr()?;
+          let expr_type = expr.get_type(&self.var_types)?;
+          if expr_type == Type::Array {
             return Err(CompileError::ParseError {
-              msg: "Expected '{' after else".to_string(),
-              span: Some(self.peek().span),
+              msg: "Functions cannot return arrays".to_string(),
+              span: Some(expr.span),
             });
           }
-
-          self.next();
-          let mut body = Vec::new();
-          while self.peek().kind != TokenKind::RBrace {
-            if self.peek().kind == TokenKind::Eof {
+          let sig = self.functions.get_mut(&fn_name).unwrap();
+          match &sig.ret {
+            Some(ret) if *ret != expr_type && sig.ret_assumed => {
               return Err(CompileError::ParseError {
-                msg: "Expected '}' after else body".to_string(),
-                span: Some(self.peek().span),
+                msg: format!(
+                  "Function '{}' returns {:?} (assumed by a call before its first return), found {:?}; annotate it with '-> type'",
+                  fn_name, ret, expr_type
+                ),
+                span: Some(expr.span),
+              });
+            }
+            Some(ret) if *ret != expr_type => {
+              return Err(CompileError::ParseError {
+                msg: format!("Function '{}' returns {:?}, found {:?}", fn_name, ret, expr_type),
+                span: Some(expr.span),
               });
             }
-            let stmt = self.parse_statement()?;
-            body.push(stmt);
+            Some(_) => {}
+            None => sig.ret = Some(expr_type),
           }
-
-          self.next();
-          Some(body)
+          Some(expr)
         } else {
           None
         };
 
-        Ok(Stmt::If { condition, then_body, else_body })
+        if self.peek().kind == TokenKind::Semicolon {
+          self.next();
+        }
+
+        Ok(StmtKind::Return(value))
       }
 
+      TokenKind::Fn => Err(CompileError::ParseError {
+        msg: "Functions can only be defined at the top level".to_string(),
+        span: Some(self.peek().span),
+      }),
+
+      TokenKind::Const => Err(CompileError::ParseError {
+        msg: "Constants can only be declared at the top level".to_string(),
+        span: Some(self.peek().span),
+      }),
+
       _ => Err(CompileError::ParseError {
         msg: format!("Unexpected token: {:?}", self.peek().kind),
         span: Some(self.peek().span),
@@ -301,6 +1148,510 @@ impl Parser {
     }
   }
 
+  /// Parses a declaration, assignment, indexed assignment or call statement without its
+  /// trailing `;`, so it can also appear in a `for` header.
+  fn parse_simple_statement(&mut self) -> Result<StmtKind, CompileError> {
+    if self.peek().kind == TokenKind::Let {
+      return self.parse_let();
+    }
+    let span = self.peek().span;
+    let TokenKind::Ident(var) = self.peek().kind.clone() else {
+      return Err(CompileError::ParseError {
+        msg: format!("Expected an assignment or call, found {:?}", self.peek().kind),
+        span: Some(span),
+      });
+    };
+    self.next();
+    if self.peek().kind == TokenKind::LParen {
+      let call = self.parse_call(var, span)?;
+      Ok(StmtKind::Expr(call))
+    } else if matches!(self.peek().kind, TokenKind::Assign | TokenKind::Colon) {
+      self.check_not_const(&var, span, "assign to")?;
+      let annotation = self.parse_annotation()?;
+      self.expect(TokenKind::Assign, "Expected '=' after variable name")?;
+      let expr = self.parse_expr()?;
+      let expr_type = expr.get_type(&self.var_types)?;
+      // The first assignment fixes the type; later ones must match it.
+      let existing = self.var_types.get(&var);
+      if let (Some(existing), Some(annotation)) = (existing, &annotation)
+        && existing != annotation
+      {
+        return Err(CompileError::ParseError {
+          msg: format!("Variable '{}' has type {:?}, but is annotated {:?}", var, existing, annotation),
+          span: Some(span),
+        });
+      }
+      Self::check_declared_type(&var, annotation.as_ref().or(existing), &expr_type, expr.span)?;
+      self.var_types.assign(&var, expr_type);
+      Ok(StmtKind::Assign { var, expr })
+    } else if let Some(op) = Self::compound_op(&self.peek().kind) {
+      self.check_not_const(&var, span, "assign to")?;
+      self.next();
+      let expr = self.parse_expr()?;
+      self.check_compound(var, span, op, expr)
+    } else if matches!(self.peek().kind, TokenKind::PlusPlus | TokenKind::MinusMinus) {
+      self.check_not_const(&var, span, "assign to")?;
+      let (op, symbol) =
+        if self.peek().kind == TokenKind::PlusPlus { (BinOp::Add, "++") } else { (BinOp::Sub, "--") };
+      let one = Expr::new(ExprKind::Int(1), self.peek().span);
+      self.next();
+      if let Some(ty) = self.var_types.get(&var).filter(|&ty| !matches!(ty, Type::Int | Type::Char)) {
+        return Err(CompileError::ParseError {
+          msg: format!("'{}' needs an int or char variable, but '{}' is {:?}", symbol, var, ty),
+          span: Some(span.to(self.prev_span)),
+        });
+      }
+      self.check_compound(var, span, op, one)
+    } else if self.peek().kind == TokenKind::LBracket {
+      if self.var_types.get(&var) != Some(&Type::Array) {
+        return Err(CompileError::ParseError { msg: format!("'{}' is not an array", var), span: Some(span) });
+      }
+      self.next();
+      let index = self.parse_expr()?;
+      if index.get_type(&self.var_types)? != Type::Int {
+        return Err(CompileError::ParseError {
+          msg: "Array index must be an integer".to_string(),
+          span: Some(index.span),
+        });
+      }
+      self.expect(TokenKind::RBracket, "Expected ']' after array index")?;
+      self.expect(TokenKind::Assign, "Expected '=' after array index")?;
+      let expr = self.parse_expr()?;
+      if expr.get_type(&self.var_types)? != Type::Int {
+        return Err(CompileError::ParseError {
+          msg: "Array elements must be integers".to_string(),
+          span: Some(expr.span),
+        });
+      }
+      Ok(StmtKind::IndexAssign { var, index, expr })
+    } else {
+      Err(CompileError::ParseError { msg: "Expected '='".to_string(), span: Some(self.peek().span) })
+    }
+  }
+
+  /// Parses a `while` or `for` loop, optionally labelled so `break`/`continue` inside can
+  /// name it.
+  fn parse_loop(&mut self, label: Option<String>) -> Result<StmtKind, CompileError> {
+    let is_while = self.peek().kind == TokenKind::While;
+    self.next();
+    if is_while {
+      let condition = self.parse_condition()?;
+      let body = self.parse_loop_body(&label, "while condition", "while-loop body")?;
+      Ok(StmtKind::While { label, condition, body })
+    } else {
+      // Variables declared in the header belong to the loop.
+      self.var_types.push();
+      let result = if self.peek().kind == TokenKind::LParen {
+        self.parse_for(label)
+      } else {
+        self.parse_for_range(label)
+      };
+      self.var_types.pop();
+      result
+    }
+  }
+
+  fn parse_loop_body(
+    &mut self,
+    label: &Option<String>,
+    after: &str,
+    body: &str,
+  ) -> Result<Vec<Stmt>, CompileError> {
+    self.loops.push(label.clone());
+    let body = self.parse_block(after, body);
+    self.loops.pop();
+    body
+  }
+
+  /// Parses `let var = expr`, declaring `var` in the innermost block. It may shadow any
+  /// variable, even one declared earlier in the same block; the initializer still sees the old one.
+  fn parse_let(&mut self) -> Result<StmtKind, CompileError> {
+    self.next();
+    let var = match &self.peek().kind {
+      TokenKind::Ident(var) => var.clone(),
+      _ => {
+        return Err(CompileError::ParseError {
+          msg: "Expected variable name after 'let'".to_string(),
+          span: Some(self.peek().span),
+        });
+      }
+    };
+    self.check_not_const(&var, self.peek().span, "redeclare")?;
+    self.next();
+    let annotation = self.parse_annotation()?;
+    self.expect(TokenKind::Assign, "Expected '=' after variable name")?;
+    let expr = self.parse_expr()?;
+    let expr_type = expr.get_type(&self.var_types)?;
+    Self::check_declared_type(&var, annotation.as_ref(), &expr_type, expr.span)?;
+    self.var_types.declare(&var, expr_type);
+    Ok(StmtKind::Let { var, expr })
+  }
+
+  /// Type-checks `var op= expr`, where `var` was found at `span`. The result must keep the
+  /// variable's type, so `s += 1` appends to a string but `n += "a"` is an error.
+  fn check_compound(&self, var: String, span: Span, op: BinOp, expr: Expr) -> Result<StmtKind, CompileError> {
+    let value_type = Expr::compound(&var, span, op.clone(), expr.clone()).get_type(&self.var_types)?;
+    Self::check_declared_type(&var, self.var_types.get(&var), &value_type, expr.span)?;
+    Ok(StmtKind::CompoundAssign { var, op, expr })
+  }
+
+  /// Parses `match value { pattern => { ... }, ... }`. A pattern is one or more integers or
+  /// `lo..=hi` ranges separated by `|`, or `_` for every value the other arms leave out, which
+  /// must come last and is required unless the arms cover every integer.
+  fn parse_match(&mut self) -> Result<StmtKind, CompileError> {
+    self.next();
+    let value = self.parse_expr()?;
+    let value_type = value.get_type(&self.var_types)?;
+    if value_type != Type::Int {
+      return Err(CompileError::ParseError {
+        msg: format!("Match value must be an integer, found {:?}", value_type),
+        span: Some(value.span),
+      });
+    }
+    self.expect(TokenKind::LBrace, "Expected '{' after match value")?;
+
+    // Mistakes in patterns are recorded without leaving the match, so the arms after them are
+    // still parsed instead of being misread as statements, and so is the code after the match.
+    let mut arms: Vec<MatchArm> = Vec::new();
+    let mut default = None;
+    while self.peek().kind != TokenKind::RBrace {
+      let unreachable = default.is_some();
+
+      let is_default = self.peek().kind == TokenKind::Underscore;
+      let mut ranges = Vec::new();
+      if is_default {
+        self.next();
+      } else {
+        loop {
+          let start = self.peek().span;
+          let (lo, hi) = self.parse_match_range()?;
+          let pattern = if lo == hi { lo.to_string() } else { format!("{}..={}", lo, hi) };
+          let mut earlier = arms.iter().flat_map(|arm| &arm.ranges).chain(&ranges);
+          if earlier.any(|&(other_lo, other_hi)| lo <= other_hi && other_lo <= hi) {
+            self.diagnostics.push(CompileError::ParseError {
+              msg: format!("Pattern '{}' overlaps an earlier pattern", pattern),
+              span: Some(self.span_from(start)),
+            });
+          } else {
+            ranges.push((lo, hi));
+          }
+          if self.peek().kind != TokenKind::Or {
+            break;
+          }
+          self.next();
+        }
+      }
+
+      self.expect(TokenKind::FatArrow, "Expected '=>' after match pattern")?;
+      let body = self.parse_block("'=>'", "match arm")?;
+      if unreachable {
+        // Reported after the '_' arm; the arm is dropped.
+      } else if is_default {
+        default = Some(body);
+      } else {
+        arms.push(MatchArm { ranges, body });
+      }
+      if self.peek().kind == TokenKind::Comma {
+        self.next();
+      }
+      if is_default && !unreachable && self.peek().kind != TokenKind::RBrace {
+        self.diagnostics.push(CompileError::ParseError {
+          msg: "The '_' arm must be the last one".to_string(),
+          span: Some(self.peek().span),
+        });
+      }
+    }
+    self.next();
+
+    if default.is_none()
+      && let Some(missing) = Self::first_unmatched(&arms)
+    {
+      self.diagnostics.push(CompileError::ParseError {
+        msg: format!("Match is not exhaustive: {} is not covered; add a '_' arm", missing),
+        span: Some(value.span),
+      });
+    }
+
+    Ok(StmtKind::Match { value, arms, default })
+  }
+
+  /// Parses an integer pattern, `n` or `lo..=hi`.
+  fn parse_match_range(&mut self) -> Result<(i32, i32), CompileError> {
+    let start = self.peek().span;
+    let lo = self.parse_match_value()?;
+    if self.peek().kind != TokenKind::DotDotEq {
+      return Ok((lo, lo));
+    }
+    self.next();
+    let hi = self.parse_match_value()?;
+    if lo > hi {
+      // It matches nothing, which is harmless to the rest of the match.
+      self.diagnostics.push(CompileError::ParseError {
+        msg: format!("Range {}..={} is empty", lo, hi),
+        span: Some(self.span_from(start)),
+      });
+    }
+    Ok((lo, hi))
+  }
+
+  /// Parses an integer literal, optionally negated, or an integer constant.
+  fn parse_match_value(&mut self) -> Result<i32, CompileError> {
+    if let TokenKind::Ident(name) = &self.peek().kind
+      && let Some(&ConstValue::Int(n)) = self.consts.get(name)
+    {
+      self.next();
+      return Ok(n);
+    }
+    let negative = self.peek().kind == TokenKind::Minus;
+    if negative {
+      self.next();
+    }
+    let TokenKind::Int(n) = self.peek().kind else {
+      return Err(CompileError::ParseError {
+        msg: format!("Expected an integer pattern, found {:?}", self.peek().kind),
+        span: Some(self.peek().span),
+      });
+    };
+    self.next();
+    Ok(if negative { n.wrapping_neg() } else { n })
+  }
+
+  /// The smallest integer no arm matches, if there is one.
+  fn first_unmatched(arms: &[MatchArm]) -> Option<i32> {
+    let mut ranges: Vec<(i32, i32)> = arms.iter().flat_map(|arm| arm.ranges.iter().copied()).collect();
+    ranges.sort();
+    // Every value below `next` is matched.
+    let mut next = i32::MIN as i64;
+    for (lo, hi) in ranges {
+      if lo as i64 > next {
+        break;
+      }
+      next = next.max(hi as i64 + 1);
+    }
+    i32::try_from(next).ok()
+  }
+
+  /// Parses `const NAME = expr` at the top level. The initializer may only combine literals and
+  /// earlier constants, and is folded to its value here so nothing is computed at run time.
+  fn parse_const(&mut self) -> Result<Stmt, CompileError> {
+    let start = self.peek().span;
+    self.next();
+    let name_span = self.peek().span;
+    let TokenKind::Ident(name) = self.peek().kind.clone() else {
+      return Err(CompileError::ParseError {
+        msg: "Expected constant name after 'const'".to_string(),
+        span: Some(name_span),
+      });
+    };
+    self.next();
+    self.check_not_const(&name, name_span, "redeclare")?;
+    if self.var_types.contains(&name) {
+      return Err(CompileError::ParseError {
+        msg: format!("'{}' is already a variable", name),
+        span: Some(name_span),
+      });
+    }
+
+    let annotation = self.parse_annotation()?;
+    self.expect(TokenKind::Assign, "Expected '=' after constant name")?;
+    let expr = self.parse_expr()?;
+    let expr_type = expr.get_type(&self.var_types)?;
+    Self::check_declared_type(&name, annotation.as_ref(), &expr_type, expr.span)?;
+    Self::check_constant(&expr)?;
+    let value = interp::eval_const(&expr);
+    if self.peek().kind == TokenKind::Semicolon {
+      self.next();
+    }
+
+    self.consts.insert(name.clone(), value.clone());
+    Ok(Stmt::new(StmtKind::Const { name, value }, self.span_from(start)))
+  }
+
+  /// Checks `expr` only combines literals and constants, so it can be evaluated while compiling.
+  fn check_constant(expr: &Expr) -> Result<(), CompileError> {
+    match &expr.kind {
+      ExprKind::Int(_)
+      | ExprKind::Bool(_)
+      | ExprKind::Char(_)
+      | ExprKind::String(_)
+      | ExprKind::Const { .. } => Ok(()),
+      ExprKind::UnaryOp { expr, .. } => Self::check_constant(expr),
+      ExprKind::Conditional { condition, then_expr, else_expr } => {
+        Self::check_constant(condition)?;
+        Self::check_constant(then_expr)?;
+        Self::check_constant(else_expr)
+      }
+      ExprKind::BinOp { left, right, .. } => {
+        Self::check_constant(left)?;
+        Self::check_constant(right)
+      }
+      _ => Err(CompileError::ParseError {
+        msg: "A constant can only be computed from literals and other constants".to_string(),
+        span: Some(expr.span),
+      }),
+    }
+  }
+
+  /// Variables may not take a constant's name, so a constant means the same value everywhere.
+  fn check_not_const(&self, name: &str, span: Span, action: &str) -> Result<(), CompileError> {
+    if self.consts.contains_key(name) {
+      return Err(CompileError::ParseError {
+        msg: format!("Cannot {} constant '{}'", action, name),
+        span: Some(span),
+      });
+    }
+    Ok(())
+  }
+
+  /// Parses the optional `: type` after a variable name.
+  fn parse_annotation(&mut self) -> Result<Option<Type>, CompileError> {
+    if self.peek().kind != TokenKind::Colon {
+      return Ok(None);
+    }
+    self.next();
+    self.parse_type().map(Some)
+  }
+
+  /// Checks a value of type `found` (spanning `span`) can be stored in `var`, if its type is
+  /// already `declared`.
+  fn check_declared_type(
+    var: &str,
+    declared: Option<&Type>,
+    found: &Type,
+    span: Span,
+  ) -> Result<(), CompileError> {
+    match declared {
+      Some(declared) if declared != found => Err(CompileError::ParseError {
+        msg: format!("Variable '{}' has type {:?}, found {:?}", var, declared, found),
+        span: Some(span),
+      }),
+      _ => Ok(()),
+    }
+  }
+
+  /// Parses `for (init; condition; update) { ... }` after the `for`. Each of the three parts may
+  /// be left empty; a missing condition is always true.
+  fn parse_for(&mut self, label: Option<String>) -> Result<StmtKind, CompileError> {
+    self.expect(TokenKind::LParen, "Expected '(' after 'for'")?;
+    let init = self.parse_for_clause()?;
+    self.expect(TokenKind::Semicolon, "Expected ';' after for-loop initializer")?;
+    let condition =
+      if self.peek().kind != TokenKind::Semicolon { Some(self.parse_condition()?) } else { None };
+    self.expect(TokenKind::Semicolon, "Expected ';' after for-loop condition")?;
+    if self.peek().kind == TokenKind::Let {
+      return Err(CompileError::ParseError {
+        msg: "A for-loop update cannot declare a variable".to_string(),
+        span: Some(self.peek().span),
+      });
+    }
+    let update = self.parse_for_clause()?;
+    self.expect(TokenKind::RParen, "Expected ')' after for-loop update")?;
+    let body = self.parse_loop_body(&label, "for-loop header", "for-loop body")?;
+
+    Ok(StmtKind::For { label, init, condition, update, body })
+  }
+
+  /// Parses the initializer or update of a `for` header: one simple statement, or none.
+  fn parse_for_clause(&mut self) -> Result<Vec<Stmt>, CompileError> {
+    if !matches!(self.peek().kind, TokenKind::Ident(_) | TokenKind::Let) {
+      return Ok(Vec::new());
+    }
+    let start = self.peek().span;
+    let kind = self.parse_simple_statement()?;
+    Ok(vec![Stmt::new(kind, self.span_from(start))])
+  }
+
+  /// Parses `for var in start..end [step n] { ... }` after the `for`. The step must be a
+  /// non-zero integer literal, since its sign decides which way the end is approached.
+  fn parse_for_range(&mut self, label: Option<String>) -> Result<StmtKind, CompileError> {
+    let var = match &self.peek().kind {
+      TokenKind::Ident(var) => var.clone(),
+      _ => {
+        return Err(CompileError::ParseError {
+          msg: "Expected a loop variable or '(' after 'for'".to_string(),
+          span: Some(self.peek().span),
+        });
+      }
+    };
+    self.check_not_const(&var, self.peek().span, "redeclare")?;
+    self.next();
+    self.expect_word("in", "Expected 'in' after loop variable")?;
+    let start = self.parse_range_bound()?;
+    self.expect(TokenKind::DotDot, "Expected '..' in range")?;
+    let end = self.parse_range_bound()?;
+
+    let step = if matches!(&self.peek().kind, TokenKind::Ident(name) if name == "step") {
+      self.next();
+      let step_span = self.peek().span;
+      let negative = self.peek().kind == TokenKind::Minus;
+      if negative {
+        self.next();
+      }
+      match self.peek().kind {
+        TokenKind::Int(n) if n != 0 => {
+          self.next();
+          if negative { -n } else { n }
+        }
+        _ => {
+          return Err(CompileError::ParseError {
+            msg: "Step must be a non-zero integer literal".to_string(),
+            span: Some(step_span.to(self.peek().span)),
+          });
+        }
+      }
+    } else {
+      1
+    };
+
+    self.var_types.declare(&var, Type::Int);
+    let body = self.parse_loop_body(&label, "range", "for-loop body")?;
+
+    Ok(StmtKind::ForRange { label, var, start, end, step, body })
+  }
+
+  fn parse_range_bound(&mut self) -> Result<Expr, CompileError> {
+    let bound = self.parse_expr()?;
+    let bound_type = bound.get_type(&self.var_types)?;
+    if bound_type != Type::Int {
+      return Err(CompileError::ParseError {
+        msg: format!("Range bounds must be integers, found {:?}", bound_type),
+        span: Some(bound.span),
+      });
+    }
+    Ok(bound)
+  }
+
+  /// Parses the condition of an `if` or `while`, which must be a `Bool`.
+  fn parse_condition(&mut self) -> Result<Expr, CompileError> {
+    let condition = self.parse_expr()?;
+    let condition_type = condition.get_type(&self.var_types)?;
+    if condition_type != Type::Bool {
+      return Err(CompileError::ParseError {
+        msg: format!("Condition must be a Bool, found {:?}", condition_type),
+        span: Some(condition.span),
+      });
+    }
+    Ok(condition)
+  }
+
+  /// The operator a compound assignment token like `+=` applies.
+  fn compound_op(kind: &TokenKind) -> Option<BinOp> {
+    let op = match kind {
+      TokenKind::PlusAssign => BinOp::Add,
+      TokenKind::MinusAssign => BinOp::Sub,
+      TokenKind::StarAssign => BinOp::Mul,
+      TokenKind::SlashAssign => BinOp::Div,
+      TokenKind::PercentAssign => BinOp::Mod,
+      TokenKind::AndAssign => BinOp::BitAnd,
+      TokenKind::OrAssign => BinOp::BitOr,
+      TokenKind::CaretAssign => BinOp::BitXor,
+      TokenKind::LShiftAssign => BinOp::LShift,
+      TokenKind::RShiftAssign => BinOp::RShift,
+      _ => return None,
+    };
+    Some(op)
+  }
+
   fn precedence(token: &Token) -> Prec {
     match token.kind {
       TokenKind::Star | TokenKind::Slash | TokenKind::Percent => Prec::MulDiv,
@@ -313,6 +1664,7 @@ impl Parser {
       TokenKind::Or => Prec::BitOr,
       TokenKind::AndAnd => Prec::And,
       TokenKind::OrOr => Prec::Or,
+      TokenKind::Question => Prec::Conditional,
       _ => Prec::Lowest,
     }
   }
@@ -322,23 +1674,24 @@ impl Parser {
   }
 
   fn parse_expr_prec(&mut self, prec: Prec) -> Result<Expr, CompileError> {
+    let start = self.peek().span;
     let mut left = match self.peek().kind {
       TokenKind::Bang => {
         self.next();
         let expr = self.parse_expr_prec(Prec::Unary)?;
-        Expr::UnaryOp { op: UnaryOp::Not, expr: Box::new(expr) }
+        Expr::new(ExprKind::UnaryOp { op: UnaryOp::Not, expr: Box::new(expr) }, self.span_from(start))
       }
       TokenKind::Minus => {
         self.next();
         let expr = self.parse_expr_prec(Prec::Unary)?;
-        Expr::UnaryOp { op: UnaryOp::Neg, expr: Box::new(expr) }
+        Expr::new(ExprKind::UnaryOp { op: UnaryOp::Neg, expr: Box::new(expr) }, self.span_from(start))
       }
       TokenKind::Tilde => {
         self.next();
         let expr = self.parse_expr_prec(Prec::Unary)?;
-        Expr::UnaryOp { op: UnaryOp::BitNot, expr: Box::new(expr) }
+        Expr::new(ExprKind::UnaryOp { op: UnaryOp::BitNot, expr: Box::new(expr) }, self.span_from(start))
       }
-      _ => self.parse_primary()?,
+      _ => self.parse_postfix()?,
     };
 
     loop {
@@ -349,6 +1702,22 @@ impl Parser {
         break;
       }
 
+      if op_token.kind == TokenKind::Question {
+        self.next();
+        let then_expr = self.parse_expr()?;
+        self.expect(TokenKind::Colon, "Expected ':' after the first value of '?'")?;
+        // The else value takes the rest, so `a ? b : c ? d : e` nests to the right.
+        let else_expr = self.parse_expr()?;
+        let span = left.span.to(else_expr.span);
+        let kind = ExprKind::Conditional {
+          condition: Box::new(left),
+          then_expr: Box::new(then_expr),
+          else_expr: Box::new(else_expr),
+        };
+        left = Expr::new(kind, span);
+        continue;
+      }
+
       let op = match op_token.kind {
         TokenKind::Plus => BinOp::Add,
         TokenKind::Minus => BinOp::Sub,
@@ -384,30 +1753,66 @@ impl Parser {
         });
       }
 
-      left = Expr::BinOp { op, left: Box::new(left), right: Box::new(right) };
+      let span = left.span.to(right.span);
+      left = Expr::new(ExprKind::BinOp { op, left: Box::new(left), right: Box::new(right) }, span);
     }
 
     Ok(left)
   }
 
+  fn parse_postfix(&mut self) -> Result<Expr, CompileError> {
+    let mut expr = self.parse_primary()?;
+    while self.peek().kind == TokenKind::LBracket {
+      self.next();
+      let index = self.parse_expr()?;
+      self.expect(TokenKind::RBracket, "Expected ']' after array index")?;
+      let span = self.span_from(expr.span);
+      expr = Expr::new(ExprKind::Index { array: Box::new(expr), index: Box::new(index) }, span);
+    }
+    Ok(expr)
+  }
+
   fn parse_primary(&mut self) -> Result<Expr, CompileError> {
-    match &self.peek().kind {
+    let start = self.peek().span;
+    let kind = match &self.peek().kind {
       TokenKind::Int(n) => {
         let val = *n;
         self.next();
-        Ok(Expr::Int(val))
+        ExprKind::Int(val)
+      }
+      TokenKind::True | TokenKind::False => {
+        let val = self.peek().kind == TokenKind::True;
+        self.next();
+        ExprKind::Bool(val)
+      }
+      TokenKind::Char(c) => {
+        let val = *c;
+        self.next();
+        ExprKind::Char(val)
       }
       TokenKind::Ident(name) => {
         let var = name.clone();
-        if !self.var_types.contains_key(&var) {
-          return Err(CompileError::ParseError { msg: format!("Variable '{}' not found", var), span: None });
+        if self.tokens.get(self.pos + 1).is_some_and(|t| t.kind == TokenKind::LParen) {
+          self.next();
+          return self.parse_call(var, start);
+        }
+        if let Some(value) = self.consts.get(&var) {
+          let value = value.clone();
+          self.next();
+          return Ok(Expr::new(ExprKind::Const { name: var, value }, start));
+        }
+        if !self.var_types.contains(&var) {
+          return Err(CompileError::ParseError {
+            msg: format!("Variable '{}' not found", var),
+            span: Some(start),
+          });
         };
         self.next();
-        Ok(Expr::Var(var))
+        ExprKind::Var(var)
       }
       TokenKind::LParen => {
         self.next();
-        let expr = self.parse_expr()?;
+        let mut expr = self.parse_expr()?;
         if self.peek().kind == TokenKind::RParen {
           self.next();
         } else {
@@ -416,32 +1821,99 @@ impl Parser {
             span: Some(self.peek().span),
           });
         }
-        Ok(expr)
+        expr.span = self.span_from(start);
+        return Ok(expr);
       }
       TokenKind::String(s) => {
         let val = s.clone();
         self.next();
-        Ok(Expr::String(val))
+        ExprKind::String(val)
       }
       TokenKind::Input => {
         self.next();
+        let mut prompt = None;
         if self.peek().kind == TokenKind::LParen {
           self.next();
-          if self.peek().kind == TokenKind::RParen {
+          if self.peek().kind != TokenKind::RParen {
+            prompt = Some(Box::new(self.parse_expr()?));
+          }
+          self.expect(TokenKind::RParen, "Expected ')' after 'input('")?;
+        }
+        ExprKind::Input(prompt)
+      }
+      TokenKind::LBracket => {
+        self.next();
+        let mut elems = Vec::new();
+        while self.peek().kind != TokenKind::RBracket {
+          elems.push(self.parse_expr()?);
+          if self.peek().kind == TokenKind::Comma {
             self.next();
-          } else {
+          } else if self.peek().kind != TokenKind::RBracket {
             return Err(CompileError::ParseError {
-              msg: "Expected ')' after 'input('".to_string(),
+              msg: "Expected ',' or ']' in array literal".to_string(),
               span: Some(self.peek().span),
             });
           }
         }
-        Ok(Expr::Input)
+        self.next();
+        ExprKind::Array(elems)
       }
-      _ => Err(CompileError::ParseError {
-        msg: format!("Unexpected token: {:?}", self.peek().kind),
-        span: Some(self.peek().span),
-      }),
+      // `if` in an expression is a conditional; like `?`, its else value takes the rest.
+      TokenKind::If => {
+        self.next();
+        let condition = self.parse_expr()?;
+        self.expect_word("then", "Expected 'then' after condition")?;
+        let then_expr = self.parse_expr()?;
+        self.expect(TokenKind::Else, "Expected 'else' after the 'then' value")?;
+        let else_expr = self.parse_expr()?;
+        ExprKind::Conditional {
+          condition: Box::new(condition),
+          then_expr: Box::new(then_expr),
+          else_expr: Box::new(else_expr),
+        }
+      }
+      _ => {
+        return Err(CompileError::ParseError {
+          msg: format!("Unexpected token: {:?}", self.peek().kind),
+          span: Some(self.peek().span),
+        });
+      }
+    };
+
+    Ok(Expr::new(kind, self.span_from(start)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+
+  /// The messages of the errors `src` is rejected with.
+  fn errors(src: &str) -> Vec<String> {
+    let tokens = Lexer::new(src).tokenize().expect("test source should lex");
+    match Parser::new(tokens).parse() {
+      Ok(_) => Vec::new(),
+      Err(errors) => errors
+        .into_iter()
+        .map(|e| match e {
+          CompileError::ParseError { msg, .. } => msg,
+          e => panic!("unexpected error: {}", e),
+        })
+        .collect(),
+    }
+  }
+
+  #[test]
+  fn arrays_cannot_be_compared() {
+    for src in ["xs = [1]; println 1 == xs;", "xs = [1]; println xs != 2;", "xs = [1]; println xs == xs;"] {
+      assert_eq!(errors(src), ["Arrays cannot be compared"], "{}", src);
     }
   }
+
+  #[test]
+  fn comparison_operands_must_match() {
+    assert_eq!(errors("println 1 == 'a';"), ["Cannot compare Int with Char"]);
+    assert!(errors("println \"a\" < \"b\"; println 'a' != 'b'; println true == false;").is_empty());
+  }
 }
@@ -0,0 +1,176 @@
+use std::io::{self, BufRead, Read, Write};
+
+use pine_rv::{
+  codegen::CodeGen,
+  error::Diagnostic,
+  interp::Interpreter,
+  lexer::{Lexer, Token, TokenKind},
+  parser::{Parser, Stmt},
+};
+
+const HELP: &str = "\
+Enter Pine statements to run them. Blocks may span several lines.
+  :type <expr>  Show the type of an expression
+  :ast          Show the parsed statements of the last input
+  :asm          Show the assembly generated for the whole session
+  :help         Show this message
+  :quit         Exit the REPL (as does Ctrl-D)";
+
+/// Reads stdin a line at a time, so `input()` never buffers past the line it consumes and the
+/// REPL can keep reading its own lines from the same stdin.
+#[derive(Default)]
+struct LineReader {
+  buf: Vec<u8>,
+  pos: usize,
+}
+
+impl Read for LineReader {
+  fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let n = available.len().min(out.len());
+    out[..n].copy_from_slice(&available[..n]);
+    self.consume(n);
+    Ok(n)
+  }
+}
+
+impl BufRead for LineReader {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    if self.pos == self.buf.len() {
+      self.buf.clear();
+      self.pos = 0;
+      io::stdin().lock().read_until(b'\n', &mut self.buf)?;
+    }
+    Ok(&self.buf[self.pos..])
+  }
+
+  fn consume(&mut self, amt: usize) {
+    self.pos = (self.pos + amt).min(self.buf.len());
+  }
+}
+
+struct Repl {
+  parser: Parser,
+  interpreter: Interpreter<LineReader, io::Stdout>,
+  /// Every statement accepted so far, which together form the program `:asm` compiles.
+  history: Vec<Stmt>,
+  last: Vec<Stmt>,
+}
+
+/// Runs the REPL until `:quit`, end of input or an `exit` statement, and returns the exit code.
+pub fn run() -> i32 {
+  let mut repl = Repl {
+    parser: Parser::new(Vec::new()),
+    interpreter: Interpreter::new(LineReader::default(), io::stdout()),
+    history: Vec::new(),
+    last: Vec::new(),
+  };
+
+  println!("Pine REPL. Type :help for commands.");
+  loop {
+    let Some(input) = read_input() else {
+      return 0;
+    };
+    let trimmed = input.trim();
+
+    if let Some(command) = trimmed.strip_prefix(':') {
+      let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+      match name {
+        "type" | "t" => repl.show_type(arg),
+        "ast" => println!("{:#?}", repl.last),
+        "asm" => repl.show_asm(),
+        "help" | "h" => println!("{}", HELP),
+        "quit" | "q" => return 0,
+        _ => eprintln!("Unknown command ':{}'. Type :help for commands.", name),
+      }
+    } else if !trimmed.is_empty()
+      && let Some(code) = repl.eval(&input)
+    {
+      return code;
+    }
+  }
+}
+
+/// Reads one input, continuing onto further lines while braces are left open. Returns `None`
+/// at the end of stdin.
+fn read_input() -> Option<String> {
+  let mut input = String::new();
+  loop {
+    print!("{}", if input.is_empty() { "pine> " } else { "....> " });
+    io::stdout().flush().ok()?;
+
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).ok()? == 0 {
+      if input.is_empty() {
+        println!();
+        return None;
+      }
+      return Some(input);
+    }
+    input.push_str(&line);
+
+    if input.trim_start().starts_with(':') || open_braces(&input) <= 0 {
+      return Some(input);
+    }
+  }
+}
+
+fn open_braces(input: &str) -> i32 {
+  // Input that does not lex yet (an unterminated string, say) is submitted as-is so the
+  // error gets reported instead of waiting forever for a closing brace.
+  let Ok(tokens) = Lexer::new(input).tokenize() else {
+    return 0;
+  };
+  tokens.iter().fold(0, |depth, token| match token.kind {
+    TokenKind::LBrace => depth + 1,
+    TokenKind::RBrace => depth - 1,
+    _ => depth,
+  })
+}
+
+impl Repl {
+  /// Parses and runs `input`. Returns the exit code if it ran an `exit` statement.
+  fn eval(&mut self, input: &str) -> Option<i32> {
+    let stmts = match tokenize(input).and_then(|tokens| self.parser.parse_more(tokens)) {
+      Ok((stmts, _)) => stmts,
+      Err(diagnostics) => {
+        report(&diagnostics, input);
+        return None;
+      }
+    };
+
+    self.history.extend(stmts.iter().cloned());
+    self.last = stmts;
+    match self.interpreter.execute(&self.last) {
+      Ok(code) => code,
+      Err(e) => {
+        eprintln!("{}", e);
+        None
+      }
+    }
+  }
+
+  fn show_type(&mut self, expr: &str) {
+    match tokenize(expr).and_then(|tokens| self.parser.expr_type(tokens).map_err(|e| vec![e])) {
+      Ok(ty) => println!("{:?}", ty),
+      Err(diagnostics) => report(&diagnostics, expr),
+    }
+  }
+
+  fn show_asm(&self) {
+    match CodeGen::new().generate(&self.history) {
+      Ok(asm) => println!("{}", asm),
+      Err(e) => eprintln!("{}", e),
+    }
+  }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+  Lexer::new(input).tokenize()
+}
+
+fn report(diagnostics: &[Diagnostic], input: &str) {
+  for diagnostic in diagnostics {
+    eprintln!("{}", diagnostic.display_with_source(input));
+  }
+}
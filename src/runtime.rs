@@ -0,0 +1,181 @@
+/// Helper routines emitted into `.text` when a program needs them. They take arguments in
+/// `a0`/`a1`, return their result in `a0` and clobber only `a0-a7` and `ra`, so values the
+/// caller keeps in `t0-t6` survive the call without being saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Routine {
+  /// `rt_strlen(s)`: number of bytes before the terminator.
+  Strlen,
+  /// `rt_strcpy(dst, src)`: copies `src` including its terminator and returns the address of
+  /// the terminator written to `dst`, so further strings can be appended there.
+  Strcpy,
+  /// `rt_concat(left, right)`: a new heap string holding `left` followed by `right`.
+  Concat,
+  /// `rt_itoa(n)`: a new heap string holding `n` in decimal.
+  Itoa,
+  /// `rt_strcmp(left, right)`: negative, zero or positive as `left` sorts before, equal to or
+  /// after `right`, comparing bytes as unsigned values.
+  Strcmp,
+  /// `rt_chr(c)`: a new heap string holding the single character `c`.
+  Chr,
+  /// `rt_readstr(max)`: reads a line of at most `max` characters into a new heap string,
+  /// without its trailing newline.
+  ReadStr,
+}
+
+impl Routine {
+  pub fn label(self) -> &'static str {
+    match self {
+      Routine::Strlen => "rt_strlen",
+      Routine::Strcpy => "rt_strcpy",
+      Routine::Concat => "rt_concat",
+      Routine::Itoa => "rt_itoa",
+      Routine::Strcmp => "rt_strcmp",
+      Routine::Chr => "rt_chr",
+      Routine::ReadStr => "rt_readstr",
+    }
+  }
+
+  /// Other routines this one calls, which must be emitted alongside it.
+  pub fn deps(self) -> &'static [Routine] {
+    match self {
+      Routine::Concat => &[Routine::Strlen, Routine::Strcpy],
+      _ => &[],
+    }
+  }
+
+  pub fn asm(self) -> &'static str {
+    match self {
+      Routine::Strlen => STRLEN,
+      Routine::Strcpy => STRCPY,
+      Routine::Concat => CONCAT,
+      Routine::Itoa => ITOA,
+      Routine::Strcmp => STRCMP,
+      Routine::Chr => CHR,
+      Routine::ReadStr => READSTR,
+    }
+  }
+}
+
+const STRLEN: &str = "\
+rt_strlen:
+  mv a1, a0 # Cursor
+rt_strlen_loop:
+  lbu a2, 0(a1)
+  beq a2, x0, rt_strlen_done
+  addi a1, a1, 1
+  j rt_strlen_loop
+rt_strlen_done:
+  sub a0, a1, a0 # Length
+  ret";
+
+const STRCPY: &str = "\
+rt_strcpy:
+  lbu a2, 0(a1)
+  sb a2, 0(a0)
+  beq a2, x0, rt_strcpy_done
+  addi a0, a0, 1
+  addi a1, a1, 1
+  j rt_strcpy
+rt_strcpy_done:
+  ret";
+
+const CONCAT: &str = "\
+rt_concat:
+  addi sp, sp, -16
+  sw ra, 12(sp) # Save return address
+  sw a0, 8(sp) # Save left string
+  sw a1, 4(sp) # Save right string
+  jal ra, rt_strlen
+  sw a0, 0(sp) # Save left length
+  lw a0, 4(sp)
+  jal ra, rt_strlen
+  lw a1, 0(sp)
+  add a0, a0, a1 # Combined length
+  addi a0, a0, 1 # Room for the terminator
+  li a7, 9 # Syscall 9: sbrk
+  ecall
+  sw a0, 0(sp) # Save result
+  lw a1, 8(sp)
+  jal ra, rt_strcpy # Copy left string
+  lw a1, 4(sp)
+  jal ra, rt_strcpy # Append right string
+  lw a0, 0(sp) # Result
+  lw ra, 12(sp) # Restore return address
+  addi sp, sp, 16
+  ret";
+
+const ITOA: &str = "\
+rt_itoa:
+  mv a4, a0 # Value to convert
+  li a0, 12 # Room for \"-2147483648\" and the terminator
+  li a7, 9 # Syscall 9: sbrk
+  ecall
+  addi a0, a0, 11 # Digits are written backwards from the end
+  sb x0, 0(a0) # Terminator
+  mv a2, a4
+  bge x0, a2, rt_itoa_loop # Work with the negated value so -2147483648 does not overflow
+  sub a2, x0, a2
+rt_itoa_loop:
+  li a3, 10
+  rem a5, a2, a3 # Negated digit
+  div a2, a2, a3
+  li a3, '0'
+  sub a5, a3, a5
+  addi a0, a0, -1
+  sb a5, 0(a0)
+  bne a2, x0, rt_itoa_loop
+  bge a4, x0, rt_itoa_done
+  li a3, '-'
+  addi a0, a0, -1
+  sb a3, 0(a0)
+rt_itoa_done:
+  ret";
+
+const STRCMP: &str = "\
+rt_strcmp:
+  lbu a2, 0(a0)
+  lbu a3, 0(a1)
+  bne a2, a3, rt_strcmp_done
+  beq a2, x0, rt_strcmp_done # Both strings ended
+  addi a0, a0, 1
+  addi a1, a1, 1
+  j rt_strcmp
+rt_strcmp_done:
+  sub a0, a2, a3 # Difference of the first mismatched bytes
+  ret";
+
+const CHR: &str = "\
+rt_chr:
+  mv a4, a0 # Character to convert
+  li a0, 2 # Room for the character and the terminator
+  li a7, 9 # Syscall 9: sbrk
+  ecall
+  sb a4, 0(a0)
+  sb x0, 1(a0) # Terminator
+  ret";
+
+const READSTR: &str = "\
+rt_readstr:
+  bge a0, x0, rt_readstr_alloc
+  li a0, 0 # A negative maximum reads nothing
+rt_readstr_alloc:
+  addi a4, a0, 1 # Room for the terminator
+  mv a0, a4
+  li a7, 9 # Syscall 9: sbrk
+  ecall
+  mv a1, a4 # read_string stores at most a1 - 1 characters, newline included if it fits
+  mv a4, a0 # Buffer
+  li a7, 8 # Syscall 8: read_string
+  ecall
+  li a3, '\\n'
+rt_readstr_loop:
+  lbu a2, 0(a0)
+  beq a2, x0, rt_readstr_done
+  beq a2, a3, rt_readstr_strip
+  addi a0, a0, 1
+  j rt_readstr_loop
+rt_readstr_strip:
+  sb x0, 0(a0) # Drop the newline
+rt_readstr_done:
+  mv a0, a4
+  ret";
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+/// Variables visible at a point in a function, one map per enclosing `{}` block. The first map
+/// is the function's own scope (or the program's, at the top level), which is where a plain
+/// assignment to a new name puts it. `let` declares into the innermost block instead, shadowing
+/// any outer variable of the same name until that block ends.
+#[derive(Debug, Clone)]
+pub struct Scopes<T> {
+  frames: Vec<HashMap<String, T>>,
+}
+
+impl<T> Default for Scopes<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T> FromIterator<(String, T)> for Scopes<T> {
+  fn from_iter<I: IntoIterator<Item = (String, T)>>(iter: I) -> Self {
+    Scopes { frames: vec![iter.into_iter().collect()] }
+  }
+}
+
+impl<T> Scopes<T> {
+  pub fn new() -> Self {
+    Scopes { frames: vec![HashMap::new()] }
+  }
+
+  /// The innermost variable called `name`.
+  pub fn get(&self, name: &str) -> Option<&T> {
+    self.frames.iter().rev().find_map(|frame| frame.get(name))
+  }
+
+  pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+    self.frames.iter_mut().rev().find_map(|frame| frame.get_mut(name))
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.get(name).is_some()
+  }
+
+  /// Declares `name` in the innermost block, returning the variable it replaces if that block
+  /// already had one.
+  pub fn declare(&mut self, name: &str, value: T) -> Option<T> {
+    self.frames.last_mut().expect("Scopes: no block is open").insert(name.to_string(), value)
+  }
+
+  /// Updates the innermost variable called `name`, or creates it in the function scope if
+  /// there is none.
+  pub fn assign(&mut self, name: &str, value: T) {
+    match self.get_mut(name) {
+      Some(slot) => *slot = value,
+      None => {
+        self.frames[0].insert(name.to_string(), value);
+      }
+    }
+  }
+
+  pub fn push(&mut self) {
+    self.frames.push(HashMap::new());
+  }
+
+  /// Closes the innermost block and returns the variables it declared.
+  pub fn pop(&mut self) -> HashMap<String, T> {
+    assert!(self.frames.len() > 1, "Scopes: cannot close the function scope");
+    self.frames.pop().unwrap()
+  }
+
+  /// The variables of the function (or top-level) scope.
+  pub fn outermost(&self) -> &HashMap<String, T> {
+    &self.frames[0]
+  }
+}
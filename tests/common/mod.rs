@@ -0,0 +1,44 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use pine_rv::{
+  codegen::CodeGen,
+  emulator::Emulator,
+  error::CompileError,
+  interp::Interpreter,
+  lexer::Lexer,
+  parser::{Parser, Stmt},
+};
+
+/// Parses `src`, which must be a valid program.
+pub fn parse(src: &str) -> Vec<Stmt> {
+  let tokens = Lexer::new(src).tokenize().unwrap_or_else(|errors| panic!("lex errors: {:?}", errors));
+  let (stmts, _) = Parser::new(tokens).parse().unwrap_or_else(|errors| panic!("parse errors: {:?}", errors));
+  stmts
+}
+
+/// Compiles `src` with `codegen` and runs it on the emulator, returning what it printed and its
+/// exit code, or the runtime error that stopped it.
+pub fn run_compiled(src: &str, mut codegen: CodeGen, input: &str) -> (String, Result<i32, String>) {
+  let asm = codegen.generate(&parse(src)).unwrap_or_else(|e| panic!("codegen error: {}", e));
+  let mut emulator = Emulator::new(&asm).unwrap_or_else(|e| panic!("assembler error: {}\n{}", e, asm));
+  let mut output = Vec::new();
+  let result = emulator.run(&mut input.as_bytes(), &mut output);
+  (String::from_utf8(output).expect("output should be UTF-8"), result.map_err(runtime_message))
+}
+
+/// Runs `src` with the interpreter, returning the same as `run_compiled`.
+pub fn run_interpreted(src: &str, input: &str) -> (String, Result<i32, String>) {
+  let stmts = parse(src);
+  let mut output = Vec::new();
+  let result = Interpreter::new(input.as_bytes(), &mut output).run(&stmts);
+  (String::from_utf8(output).expect("output should be UTF-8"), result.map_err(runtime_message))
+}
+
+fn runtime_message(error: CompileError) -> String {
+  match error {
+    CompileError::RuntimeError { msg } => msg,
+    e => panic!("unexpected error: {}", e),
+  }
+}
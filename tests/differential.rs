@@ -0,0 +1,163 @@
+//! The interpreter is the reference for the generated assembly: every program here must print
+//! the same and exit with the same code under both.
+
+mod common;
+
+use std::thread;
+
+use pine_rv::codegen::CodeGen;
+
+/// Runs `src` on both backends with `input` and checks they agree, returning the output.
+fn check(src: &str, input: &str) -> String {
+  let interpreted = common::run_interpreted(src, input);
+  let compiled = common::run_compiled(src, CodeGen::new(), input);
+  assert_eq!(compiled, interpreted, "compiled and interpreted runs differ");
+  interpreted.0
+}
+
+#[test]
+fn loops() {
+  let output = check(
+    "total = 0;
+     i = 0;
+     while i < 10 { i += 1; if i % 3 == 0 { continue; } total += i; }
+     for (j = 0; j < 5; j++) { total -= j; }
+     for k in 10..0 step -3 { print k; print ' '; }
+     println total;
+     outer: for a in 0..5 { for b in 0..5 { if a * b > 6 { break outer; } print a * b; } }
+     println;",
+    "",
+  );
+  assert_eq!(output, "10 7 4 1 27\n00000012340246\n");
+}
+
+#[test]
+fn arrays() {
+  let output = check(
+    "fn sum(xs: array) -> int { s = 0; for i in 0..len(xs) { s += xs[i]; } return s; }
+     fn fill(xs: array, v) { for i in 0..len(xs) { xs[i] = v * i; } }
+     xs = array(5);
+     fill(xs, 3);
+     ys = xs;
+     ys[0] = 100;
+     keep = [0];
+     for i in 0..3 { a = [i, i * 2]; if i == 1 { keep = a; } }
+     println sum(xs) + xs[0] + keep[1];",
+    "",
+  );
+  assert_eq!(output, "232\n");
+}
+
+#[test]
+fn strings() {
+  check(
+    "s = \"pine\";
+     t = s + \"-\" + 42 + \"-\" + true + \"-\" + 'c' + \"-\" + -7;
+     println t;
+     println s < \"pinf\";
+     println s == \"pi\" + \"ne\";
+     println \"tab\\there\\n\" + \"end\";
+     t += \"!\";
+     println t;",
+    "",
+  );
+}
+
+#[test]
+fn non_ascii_strings() {
+  let output = check("println \"héllo ☃\"; s = \"ü\" + 1; println s;", "");
+  assert_eq!(output, "héllo ☃\nü1\n");
+}
+
+#[test]
+fn chars() {
+  check(
+    "c = 'a';
+     c += 2;
+     println c;
+     println c - 'a';
+     println c + 1 > 'c';
+     for i in 0..5 { print 'a' + i; }
+     println;
+     println '\\'' + \"\" + '\\\\';",
+    "",
+  );
+}
+
+#[test]
+fn bools() {
+  check(
+    "t = true;
+     f = !t;
+     println t && f || !f;
+     println (1 < 2) == (3 > 4);
+     println f ? \"yes\" : \"no\";
+     x = 0;
+     println x != 0 && 10 / x > 0;",
+    "",
+  );
+}
+
+#[test]
+fn match_lowerings() {
+  // A dense match is lowered to a jump table, a sparse one to a compare chain.
+  let src = "fn dense(n) -> string {
+       match n { 0 => { return \"zero\"; } 1 | 2 => { return \"small\"; } 3..=5 => { return \"mid\"; }
+                 6 => { return \"six\"; } _ => { return \"other\"; } }
+     }
+     fn sparse(n) -> string {
+       match n { -100 => { return \"low\"; } 7 => { return \"seven\"; } 1000..=2000 => { return \"high\"; }
+                 _ => { return \"other\"; } }
+     }
+     for i in -1..8 { print dense(i); print ' '; }
+     println;
+     ns = [-100, 7, 1000, 1500, 2000, 2001, 0];
+     for i in 0..len(ns) { print sparse(ns[i]); print ' '; }
+     println;";
+  let asm = CodeGen::new().generate(&common::parse(src)).unwrap();
+  assert_eq!(asm.matches("_table:").count(), 1, "expected only the dense match to use a table");
+  let output = check(src, "");
+  assert!(output.starts_with("other zero small small mid mid mid six other"), "{}", output);
+}
+
+#[test]
+fn input_builtins() {
+  let output = check(
+    "n = input();
+     name = input_str();
+     short = input_str(3);
+     c = getc();
+     d = getc();
+     println n * 2;
+     println name + \"|\" + short + \"|\" + c + d;",
+    "21\nPine\nabcdef\nxy\n",
+  );
+  assert_eq!(output, "42\nPine|abc|xy\n");
+}
+
+#[test]
+fn exit_codes() {
+  check("println 1; exit 3; println 2;", "");
+  check("fn f(x) { if x > 2 { exit x; } f(x + 1); } f(0);", "");
+}
+
+#[test]
+fn bounds_failures() {
+  check("xs = [1, 2, 3]; println xs[2]; println xs[3];", "");
+  check("xs = array(4); i = -1; xs[i] = 5;", "");
+  check("fn get(xs: array, i) -> int { return xs[i]; } println get([1], 0) + get([], 0);", "");
+}
+
+#[test]
+fn interpreter_limits_call_depth() {
+  let src = "fn f(n) { if n == 0 { return 0; } return 1 + f(n - 1); } println f(100000);";
+  let (output, code) = common::run_compiled(src, CodeGen::new(), "");
+  assert_eq!((output.as_str(), code), ("100000\n", Ok(0)));
+
+  // The interpreter recurses natively, so give it the stack the CLI does.
+  let interpreted =
+    thread::Builder::new().stack_size(512 << 20).spawn(move || common::run_interpreted(src, ""));
+  let (output, code) = interpreted.unwrap().join().unwrap();
+  assert_eq!(output, "");
+  assert_eq!(code, Err("Call to 'f' exceeds the limit of 10000 nested calls".to_string()));
+}
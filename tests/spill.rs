@@ -0,0 +1,90 @@
+//! Programs compiled with only three temporaries, so that expressions spill to the stack, must
+//! print the same as with the full register pool.
+
+mod common;
+
+use pine_rv::codegen::CodeGen;
+
+/// Runs `src` with three registers and with all seven, checking both print `expected`.
+fn check(src: &str, expected: &str) {
+  for regs in [3, 7] {
+    let (output, code) = common::run_compiled(src, CodeGen::new().with_regs(regs), "");
+    assert_eq!(output, expected, "with {} registers", regs);
+    assert_eq!(code, Ok(0), "with {} registers", regs);
+  }
+}
+
+#[test]
+fn deep_arithmetic() {
+  check(
+    "a = 1; b = 2; c = 3; d = 4;
+     println ((a + b) * (c + d)) - ((a * b) + (c * d) * ((a + c) - (b - d)));
+     println (a + (b + (c + (d + (a + (b + (c + d))))))) * ((d - c) * (b - a));",
+    "-53\n20\n",
+  );
+}
+
+#[test]
+fn calls_inside_expressions() {
+  check(
+    "fn add(x, y) -> int { return x + y; }
+     fn sq(x) -> int { return x * x; }
+     a = 3;
+     println add(sq(a) + add(1, 2), sq(add(a, 1)) * (a + add(a, a)));
+     println (a + 1) * add(a, add(a, add(a, sq(a)))) - sq(2);",
+    "156\n68\n",
+  );
+}
+
+#[test]
+fn short_circuit_operators() {
+  check(
+    "a = 1; b = 2; c = 3;
+     println (a + b > c || b * c == 6) && (a - b < 0 && !(c == a + b + 1));
+     x = 0;
+     println x != 0 && 10 / x > 1;
+     println (a < b) == (b < c && (a + b + c) * (a + b) > 17);",
+    "true\nfalse\ntrue\n",
+  );
+}
+
+#[test]
+fn conditional_expressions() {
+  check(
+    "a = 5; b = -3;
+     println (a > b ? a * (b + 10) : b) + (b > 0 ? 1 : a > 0 ? (a + b) * (a - b) : 0);
+     println 1 + (if a > 0 then (a + 1) * (a + 2) else 0) * (a > 1 ? 2 : 3);",
+    "51\n85\n",
+  );
+}
+
+#[test]
+fn array_operands() {
+  check(
+    "xs = [1, 2, 3, 4];
+     i = 1;
+     println xs[i] + xs[i + 1] * (xs[0] + xs[xs[0] + 2]) - len(xs);
+     xs[xs[0]] = (xs[2] + xs[3]) * (xs[1] - xs[0]);
+     println xs[1] + [5, 6][1] * (len([1, 2, 3]) + xs[3]);",
+    "13\n49\n",
+  );
+}
+
+#[test]
+fn string_operands() {
+  check(
+    "name = \"pine\";
+     n = 3;
+     println \"a\" + (n + 1) + (\"b\" + name + 'c') + (n > 2) + (n * (n + 1));
+     println (\"x\" + n < \"x\" + (n + 1)) == (name + \"!\" != \"pine\");",
+    "a4bpinectrue12\ntrue\n",
+  );
+}
+
+#[test]
+fn three_registers_force_spills() {
+  let stmts = common::parse("a = 1; println a + (a + (a + (a + a)));");
+  let asm = CodeGen::new().with_regs(3).generate(&stmts).unwrap();
+  assert!(asm.contains("# Spill t"), "{}", asm);
+  assert!(asm.contains("# Reload spilled value"), "{}", asm);
+}